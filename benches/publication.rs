@@ -0,0 +1,106 @@
+//! Receiving loop's packet rate with the statistics behind a lock versus published lock-free
+//!
+//! Frames go to a `FrameHandler` as fast as it takes them while a reporter
+//! reads the statistics every millisecond. Run with `cargo bench --bench publication`.
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use loss_tester_rs::{
+    frames::{FrameBuilder, FrameHandler},
+    framing::CobsFraming,
+    report::{Report, ReportCell},
+};
+
+/// Frames handled per run
+const FRAMES: usize = 1_000_000;
+
+/// Size of the frames, a minimal Ethernet frame's UDP payload
+const FRAME_SIZE: u16 = 18;
+
+/// Interval between the reporter's reads, far more often than the loop's reports
+const READ_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Interval between publications, as in the receiving loop
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Frames a receive call passes to the handler, as with the default `--batch`
+const BATCH: usize = 32;
+
+fn frames() -> Vec<Vec<u8>> {
+    let mut builder = FrameBuilder::new(FRAME_SIZE, Box::new(CobsFraming::new()));
+    (0..FRAMES).map(|_| builder.next().to_vec()).collect()
+}
+
+/// Runs `receive` along with a reporter calling `read`, returns frames per second
+fn measure(receive: impl FnOnce(), read: impl Fn() + Sync) -> f64 {
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                read();
+                std::thread::sleep(READ_INTERVAL);
+            }
+        });
+        let start = Instant::now();
+        receive();
+        let elapsed = start.elapsed();
+        done.store(true, Ordering::Relaxed);
+        FRAMES as f64 / elapsed.as_secs_f64()
+    })
+}
+
+/// Handler behind a lock taken for every frame, as the loop used to do
+fn locked(frames: &[Vec<u8>]) -> f64 {
+    let handler = Arc::new(RwLock::new(FrameHandler::new(Box::new(CobsFraming::new()))));
+    measure(
+        || {
+            for frame in frames {
+                handler.write().unwrap().handle(frame);
+            }
+        },
+        || {
+            std::hint::black_box(Report::of(&handler.read().unwrap()));
+        },
+    )
+}
+
+/// Handler owned by the loop, which publishes its report between batches
+fn published(frames: &[Vec<u8>]) -> f64 {
+    let cell = ReportCell::new();
+    measure(
+        || {
+            let mut handler = FrameHandler::new(Box::new(CobsFraming::new()));
+            let mut last_publish = Instant::now();
+            for batch in frames.chunks(BATCH) {
+                for frame in batch {
+                    handler.handle(frame);
+                }
+                if last_publish.elapsed() >= PUBLISH_INTERVAL {
+                    last_publish = Instant::now();
+                    cell.publish(Report::of(&handler).as_ref());
+                }
+            }
+        },
+        || {
+            std::hint::black_box(cell.read());
+        },
+    )
+}
+
+fn main() {
+    let frames = frames();
+    for _ in 0..3 {
+        let locked = locked(&frames);
+        let published = published(&frames);
+        println!(
+            "{FRAMES} frames of {FRAME_SIZE} bytes: RwLock {locked:.0} pps, \
+             lock-free {published:.0} pps ({:+.1}%)",
+            (published / locked - 1.) * 100.
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Packet rate of batched UDP receives with slots of the MTU versus slots of the largest datagram
+//!
+//! Datagrams of every size are queued on a loopback socket a batch at a time and
+//! only their reception is timed. Run with `cargo bench --bench receive`.
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use loss_tester_rs::protocols::{SocketOptions, UdpReceiver};
+
+/// Datagrams received with one call, as the receiver's default
+const BATCH: usize = 32;
+
+/// UDP payloads of minimal, 1500 byte and jumbo frames
+const SIZES: [u16; 3] = [18, 1472, 8972];
+
+/// IP and UDP headers on top of the payload
+const HEADERS: u16 = 28;
+
+/// Slot size of the receiver before, the largest UDP payload
+const LARGEST: u16 = u16::MAX;
+
+/// Receiver's port, the senders use ephemeral ones
+const PORT: u16 = 5999;
+
+/// Time spent on every measurement
+const DURATION: Duration = Duration::from_millis(500);
+
+/// Receives batches of `size` byte datagrams into slots of `mtu` bytes, returns datagrams per second
+fn measure(size: u16, mtu: u16) -> f64 {
+    let options = SocketOptions {
+        // a whole batch of jumbo frames has to fit
+        recv_buffer: Some(4 << 20),
+        ..Default::default()
+    };
+    let mut receiver = UdpReceiver::new(
+        Ipv4Addr::LOCALHOST,
+        PORT,
+        Ipv4Addr::LOCALHOST,
+        BATCH,
+        mtu,
+        &options,
+    )
+    .expect("receiver");
+    let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("sender");
+    sender
+        .connect((Ipv4Addr::LOCALHOST, PORT))
+        .expect("connect");
+    let datagram = vec![1; size.into()];
+    let mut received = 0;
+    let mut receiving = Duration::ZERO;
+    while receiving < DURATION {
+        for _ in 0..BATCH {
+            sender.send(&datagram).expect("send");
+        }
+        let start = Instant::now();
+        let mut pending = BATCH;
+        while pending > 0 {
+            let frames = receiver
+                .recv_from_batch(&mut |_, frame, _| assert_eq!(frame.len(), size.into()))
+                .expect("receive");
+            pending -= frames;
+        }
+        receiving += start.elapsed();
+        received += BATCH;
+    }
+    received as f64 / receiving.as_secs_f64()
+}
+
+fn main() {
+    for size in SIZES {
+        let before = measure(size, LARGEST);
+        let after = measure(size, size + HEADERS);
+        println!(
+            "{size: >5} bytes: {LARGEST} byte slots {:5.2} Mpps, {} byte slots {:5.2} Mpps, {:.2}x",
+            before / 1e6,
+            size + HEADERS,
+            after / 1e6,
+            after / before
+        );
+    }
+}
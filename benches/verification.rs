@@ -0,0 +1,75 @@
+//! Throughput of frame verification per core, decoding and comparing chunks versus checking in place
+//!
+//! Run with `cargo bench --bench verification`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use loss_tester_rs::{
+    frames::FrameBuilder,
+    framing::{CobsFraming, FixedFraming, Framing, LengthFraming},
+};
+
+/// Frame sizes measured, from minimal to jumbo
+const SIZES: [u16; 3] = [64, 1500, 9000];
+
+/// Distinct records handled in turn
+const RECORDS: usize = 1024;
+
+/// Sequence filling the frame data, as the frame builder does
+const SEQUENCE: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// Creates framing for records of the given size
+type NewFraming = fn(usize) -> Box<dyn Framing>;
+
+/// Verification as it used to be: decode into a copy, then compare it chunk by chunk
+fn chunked(framing: &dyn Framing, record: &[u8], scratch: &mut [u8]) -> bool {
+    let Some(payload) = framing.decode(record, scratch) else {
+        return false;
+    };
+    payload.len() >= 4
+        && payload[4..]
+            .chunks(SEQUENCE.len())
+            .all(|chunk| chunk == &SEQUENCE[..chunk.len()])
+}
+
+fn verification(c: &mut Criterion) {
+    let framings: [(&str, NewFraming); 3] = [
+        ("cobs", |_| Box::new(CobsFraming::new())),
+        ("length", |_| Box::new(LengthFraming::new())),
+        ("fixed", |size| Box::new(FixedFraming::new(size))),
+    ];
+    for (name, framing) in framings {
+        let mut group = c.benchmark_group(name);
+        for size in SIZES {
+            let mut builder = FrameBuilder::new(size, framing(size.into()));
+            let records: Vec<Vec<u8>> = (0..RECORDS).map(|_| builder.next().to_vec()).collect();
+            let mut scratch = vec![0; 65536];
+            let framing = framing(size.into());
+            group.throughput(Throughput::Bytes(u64::from(size) * RECORDS as u64));
+            group.bench_with_input(BenchmarkId::new("chunked", size), &records, |b, records| {
+                b.iter(|| {
+                    for record in records {
+                        assert!(chunked(&*framing, record, &mut scratch));
+                    }
+                })
+            });
+            group.bench_with_input(
+                BenchmarkId::new("in place", size),
+                &records,
+                |b, records| {
+                    b.iter(|| {
+                        for record in records {
+                            assert!(matches!(
+                                framing.verify(record, &mut scratch),
+                                Some((_, true))
+                            ));
+                        }
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, verification);
+criterion_main!(benches);
@@ -0,0 +1,223 @@
+/// Module for measuring how well the sender keeps to its target
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    protocols::ProtoError,
+    rate::Bitrate,
+};
+
+/// ENOBUFS, which has no `ErrorKind` of its own
+#[cfg(target_os = "linux")]
+const ENOBUFS: i32 = libc::ENOBUFS;
+/// WSAENOBUFS
+#[cfg(windows)]
+const ENOBUFS: i32 = 10055;
+/// ENOBUFS of BSD and macOS
+#[cfg(not(any(target_os = "linux", windows)))]
+const ENOBUFS: i32 = 55;
+
+/// Kind of a failed send
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// ENOBUFS, queue of the interface or qdisc is full
+    NoBuffers,
+    /// EAGAIN, socket buffer is full
+    WouldBlock,
+    /// ECONNREFUSED, receiver's port was unreachable, reported for an earlier datagram
+    Refused,
+    Other,
+}
+
+impl SendError {
+    pub fn of(err: &ProtoError) -> Self {
+        let ProtoError::IOErr(err) = err else {
+            return Self::Other;
+        };
+        match err.kind() {
+            std::io::ErrorKind::WouldBlock => Self::WouldBlock,
+            std::io::ErrorKind::ConnectionRefused => Self::Refused,
+            _ if err.raw_os_error() == Some(ENOBUFS) => Self::NoBuffers,
+            _ => Self::Other,
+        }
+    }
+    /// Whether the send may succeed when retried
+    pub fn is_transient(&self) -> bool {
+        !matches!(self, Self::Other)
+    }
+}
+
+/// Send errors counted by kind
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SendErrors {
+    pub no_buffers: u64,
+    pub would_block: u64,
+    pub refused: u64,
+    pub other: u64,
+    /// Frames given up on after retries
+    pub dropped: u64,
+}
+
+impl SendErrors {
+    fn count(&mut self, kind: SendError) {
+        match kind {
+            SendError::NoBuffers => self.no_buffers += 1,
+            SendError::WouldBlock => self.would_block += 1,
+            SendError::Refused => self.refused += 1,
+            SendError::Other => self.other += 1,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for SendErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ENOBUFS, {} EAGAIN, {} ECONNREFUSED, {} other, {} frames dropped",
+            self.no_buffers, self.would_block, self.refused, self.other, self.dropped
+        )
+    }
+}
+
+/// Sender's achieved rate, time split and errors over a report interval
+pub struct SendAccuracy<C: Clock = SystemClock> {
+    start: Instant,
+    frames: u64,
+    bytes: u64,
+    sending: Duration,
+    sleeping: Duration,
+    errors: SendErrors,
+    total_errors: SendErrors,
+    /// Datagrams stamped on their way through the qdisc, the sum and longest of their delays
+    qdisc: (u64, Duration, Duration),
+    clock: C,
+}
+
+impl SendAccuracy {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl Default for SendAccuracy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> SendAccuracy<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            start: clock.now(),
+            frames: 0,
+            bytes: 0,
+            sending: Duration::ZERO,
+            sleeping: Duration::ZERO,
+            errors: SendErrors::default(),
+            total_errors: SendErrors::default(),
+            qdisc: (0, Duration::ZERO, Duration::ZERO),
+            clock,
+        }
+    }
+    /// `frames` totalling `bytes` were sent, which took `took`
+    pub fn sent(&mut self, frames: usize, bytes: usize, took: Duration) {
+        self.frames += frames as u64;
+        self.bytes += bytes as u64;
+        self.sending += took;
+    }
+    pub fn slept(&mut self, took: Duration) {
+        self.sleeping += took;
+    }
+    pub fn failed(&mut self, kind: SendError) {
+        self.errors.count(kind);
+        self.total_errors.count(kind);
+    }
+    /// A datagram spent `delay` between entering the qdisc and reaching the driver
+    pub fn transmitted(&mut self, delay: Duration) {
+        let (count, sum, max) = &mut self.qdisc;
+        *count += 1;
+        *sum += delay;
+        *max = (*max).max(delay);
+    }
+    pub fn dropped(&mut self) {
+        self.errors.dropped += 1;
+        self.total_errors.dropped += 1;
+    }
+    /// Errors of the whole session
+    pub fn total_errors(&self) -> SendErrors {
+        self.total_errors
+    }
+    /// Describes the interval since the previous report and starts a new one
+    pub fn report(&mut self, average: Bitrate, target: Option<Bitrate>) -> String {
+        let elapsed = self.clock.since(self.start).as_secs_f64().max(f64::EPSILON);
+        let achieved = Bitrate((self.bytes as f64 * 8. / elapsed) as u64);
+        let pps = self.frames as f64 / elapsed;
+        let mut report = format!("Send speed: {achieved} ({pps:.0} pps), avg: {average}");
+        if let Some(target) = target.filter(|target| target.0 != 0) {
+            let deviation = (achieved.0 as f64 / target.0 as f64 - 1.) * 100.;
+            report += &format!(", target: {target} ({deviation:+.2}%)");
+        }
+        report += &format!(
+            ", sleeping {:.1}%, sending {:.1}%",
+            self.sleeping.as_secs_f64() / elapsed * 100.,
+            self.sending.as_secs_f64() / elapsed * 100.
+        );
+        if let (count @ 1.., sum, max) = self.qdisc {
+            report += &format!(
+                ", qdisc delay {}us (max {}us)",
+                sum.as_micros() / u128::from(count),
+                max.as_micros()
+            );
+        }
+        if !self.errors.is_empty() {
+            report += &format!(", errors: {}", self.errors);
+        }
+        self.start = self.clock.now();
+        self.frames = 0;
+        self.bytes = 0;
+        self.sending = Duration::ZERO;
+        self.sleeping = Duration::ZERO;
+        self.errors = SendErrors::default();
+        self.qdisc = (0, Duration::ZERO, Duration::ZERO);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_report() {
+        let clock = MockClock::new();
+        let mut accuracy = SendAccuracy::with_clock(clock.clone());
+        for _ in 0..100 {
+            accuracy.sent(1, 1000, Duration::from_millis(1));
+            accuracy.slept(Duration::from_millis(4));
+            clock.advance(Duration::from_millis(5));
+        }
+        let refused = ProtoError::IOErr(std::io::ErrorKind::ConnectionRefused.into());
+        accuracy.failed(SendError::of(&refused));
+        let report = accuracy.report(Bitrate(1_000_000), Some(Bitrate(2_000_000)));
+        assert_eq!(
+            report,
+            "Send speed: 1.60 Mbit/s (200 pps), avg: 1.00 Mbit/s, target: 2.00 Mbit/s (-20.00%), \
+             sleeping 80.0%, sending 20.0%, \
+             errors: 0 ENOBUFS, 0 EAGAIN, 1 ECONNREFUSED, 0 other, 0 frames dropped"
+        );
+        // interval errors start over, session ones stay
+        accuracy.transmitted(Duration::from_micros(10));
+        accuracy.transmitted(Duration::from_micros(30));
+        let report = accuracy.report(Bitrate(0), None);
+        assert!(!report.contains("errors"));
+        assert!(report.ends_with("qdisc delay 20us (max 30us)"));
+        assert_eq!(accuracy.total_errors().refused, 1);
+    }
+}
@@ -2,6 +2,13 @@ use std::net::Ipv4Addr;
 
 use clap::{Parser, Subcommand, ValueEnum};
 
+use crate::{
+    protocols::{Dscp, PartialReliability},
+    rate::Bitrate,
+    search::DEFAULT_FRAME_SIZES,
+    speed_controllers::{BucketDepth, Profile, Traffic},
+};
+
 #[derive(Subcommand)]
 #[command(infer_subcommands = true)]
 pub enum Commands {
@@ -17,6 +24,30 @@ pub enum Commands {
         #[arg(short = 'I', long, default_value_t = 1)]
         /// Interval between reports
         interval: u8,
+
+        #[arg(short, long, default_value_t = 1500)]
+        /// Maximum Transmission Unit, has to match the client for fixed framing, longer UDP datagrams are truncated without GRO
+        mtu: u16,
+
+        #[arg(long, default_value_t = 0)]
+        /// Interval between statistics sent back to the client, ms (0 for final results only, AIMD needs it)
+        feedback: u64,
+
+        #[arg(long)]
+        /// Let the kernel coalesce received datagrams with UDP_GRO, Linux UDP only
+        gro: bool,
+
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=1024))]
+        /// Receiving threads, each with its own SO_REUSEPORT socket and the flows the kernel hashes to it, UDP only
+        workers: u16,
+
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u16).range(..1024))]
+        /// CPUs to pin the receiving threads to, round robin, Linux only
+        cpus: Vec<u16>,
+
+        #[arg(long)]
+        /// Report the host's UDP receive buffer and input errors of /proc/net/snmp every interval, Linux only
+        host_drops: bool,
     },
     /// Client mode
     Client {
@@ -27,13 +58,98 @@ pub enum Commands {
         #[arg(default_value_t = 5000)]
         port: u16,
 
-        #[arg(short, long, default_value_t = 1000)]
-        /// Limit transmission bandwidth, kbit/s (0 to disable limiting)
-        bandwidth: u32,
+        #[arg(short, long, default_value = "1M")]
+        /// Limit transmission bandwidth, bit/s with k, M, G, Ki, Mi, Gi prefixes and B suffix for bytes (0 to disable limiting, 1M for UDP)
+        bandwidth: Bitrate,
+
+        #[arg(long, conflicts_with = "bandwidth")]
+        /// Limit transmission to a packet rate regardless of frame size
+        pps: Option<u64>,
+
+        #[arg(long, conflicts_with_all = ["bandwidth", "pps"])]
+        /// Vary bandwidth over time: ramp:FROM:TO:SECS, steps:FROM:TO:STEP:DWELL, sawtooth:FROM:TO:PERIOD or file:PATH with `<seconds> <rate>` lines
+        profile: Option<Profile>,
 
         #[arg(short, long, default_value_t = 1500)]
         /// Maximum Transmission Unit
         mtu: u16,
+
+        #[arg(short = 'L', long, value_enum, default_value_t = LimiterKind::BURST)]
+        /// Speed limiting strategy
+        limiter: LimiterKind,
+
+        #[arg(long, value_enum, default_value_t = Pacing::USER)]
+        /// Who paces the burst limiter's frames: user space, the kernel with SO_MAX_PACING_RATE (fq qdisc for UDP) or SO_TXTIME launch times (fq or etf qdisc, UDP), Linux only
+        pacing: Pacing,
+
+        #[arg(long, default_value = "10f")]
+        /// Token bucket depth, bytes or frames with `f` suffix
+        bucket: BucketDepth,
+
+        #[arg(long, default_value = "10M")]
+        /// AIMD rate increase every second without loss, starts from bandwidth
+        increase: Bitrate,
+
+        #[arg(long, default_value_t = 0.5)]
+        /// AIMD rate multiplier on loss
+        decrease: f64,
+
+        #[arg(long, default_value = "exp")]
+        /// Random inter-departure times: exp (Poisson), pareto:SHAPE or onoff:ON:OFF with mean periods in seconds
+        traffic: Traffic,
+
+        #[arg(long)]
+        /// Seed of the random limiter, picked randomly if not set
+        seed: Option<u64>,
+
+        #[arg(long, required_if_eq("limiter", "trace"))]
+        /// Trace to replay: pcap or text with `<seconds> <IP packet length>` lines
+        trace: Option<String>,
+
+        #[arg(long = "loop")]
+        /// Replay the trace over and over
+        looped: bool,
+
+        #[arg(long)]
+        /// Take `rate <bitrate>`, `pause`, `resume`, `mtu <bytes>` and `stats` commands from stdin (`-`) or a Unix socket at the path
+        control: Option<String>,
+
+        #[arg(long)]
+        /// Hand batches of equally sized frames to the kernel as single buffers, split with UDP_SEGMENT, Linux UDP only
+        gso: bool,
+
+        #[arg(long)]
+        /// Report how long datagrams wait in the qdisc, from SO_TIMESTAMPING transmit timestamps, Linux UDP only
+        tx_timestamps: bool,
+    },
+    /// Search for the highest lossless rate, RFC 2544 style
+    Search {
+        /// IP address to connect to
+        addr: Ipv4Addr,
+
+        /// Port to connect to
+        #[arg(default_value_t = 5000)]
+        port: u16,
+
+        #[arg(short, long, value_delimiter = ',', default_values_t = DEFAULT_FRAME_SIZES)]
+        /// Ethernet frame sizes to search for
+        sizes: Vec<u16>,
+
+        #[arg(short, long, default_value = "1G")]
+        /// Highest Ethernet bitrate to try
+        max: Bitrate,
+
+        #[arg(short, long, default_value_t = 10)]
+        /// Duration of a single trial, seconds
+        duration: u64,
+
+        #[arg(short, long, default_value_t = 0.)]
+        /// Highest loss of a passed trial, percent
+        threshold: f64,
+
+        #[arg(short, long, default_value_t = 1.)]
+        /// Stop the search when the range is narrower than this, percent of the rate
+        resolution: f64,
     },
 }
 
@@ -41,6 +157,66 @@ pub enum Commands {
 pub enum Proto {
     UDP,
     TCP,
+    /// Multipath TCP, falls back to TCP where the kernel lacks it
+    MPTCP,
+    /// SCTP over one-to-one sockets, Linux only
+    SCTP,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum LimiterKind {
+    BURST,
+    TOKEN,
+    AIMD,
+    RANDOM,
+    TRACE,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum Pacing {
+    USER,
+    FQ,
+    TXTIME,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum IoBackend {
+    STD,
+    URING,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum Ecn {
+    #[value(name = "not-ect")]
+    NOTECT,
+    ECT1,
+    ECT0,
+    CE,
+}
+
+impl Ecn {
+    /// Codepoint in the lower 2 bits of the TOS byte
+    pub fn codepoint(&self) -> u8 {
+        match self {
+            Ecn::NOTECT => 0b00,
+            Ecn::ECT1 => 0b01,
+            Ecn::ECT0 => 0b10,
+            Ecn::CE => 0b11,
+        }
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum Df {
+    SET,
+    CLEAR,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum FrameFormat {
+    COBS,
+    LENGTH,
+    FIXED,
 }
 
 /// Program to detect network packet loss and packet mangling
@@ -54,7 +230,83 @@ pub struct Args {
     /// Protocol to send data over
     pub proto: Proto,
 
+    #[arg(short = 'F', long, value_enum, default_value_t = FrameFormat::COBS)]
+    /// Framing of records: zero-delimited COBS, magic with 32-bit length or fixed size
+    pub framing: FrameFormat,
+
     #[arg(short = 'B', long, default_value_t = Ipv4Addr::UNSPECIFIED)]
     /// IP address to bind to
     pub bind: Ipv4Addr,
+
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u16).range(1..=1024))]
+    /// Frames sent or received with one call: sendmmsg/recvmmsg for UDP, io_uring for both protocols, Linux only (1 to disable)
+    pub batch: u16,
+
+    #[arg(long, value_enum, default_value_t = IoBackend::STD)]
+    /// Socket I/O: blocking std::net calls or io_uring with registered buffers and multishot receive (needs the io-uring feature, Linux only)
+    pub io: IoBackend,
+
+    #[arg(long)]
+    /// Socket send buffer size with SO_SNDBUF, bytes, the kernel doubles it (Linux only)
+    pub sndbuf: Option<u32>,
+
+    #[arg(long)]
+    /// Socket receive buffer size with SO_RCVBUF, bytes, the kernel doubles it (Linux only)
+    pub rcvbuf: Option<u32>,
+
+    #[arg(long)]
+    /// DSCP of sent packets: 0-63 or a name, EF, AF11-AF43, CS0-CS7, VA, LE, BE (Linux only)
+    pub dscp: Option<Dscp>,
+
+    #[arg(long, value_enum)]
+    /// ECN codepoint of sent packets, TCP keeps managing its own (Linux only)
+    pub ecn: Option<Ecn>,
+
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    /// TTL of sent unicast packets (Linux only)
+    pub ttl: Option<u8>,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    /// TTL of sent multicast packets
+    pub multicast_ttl: u8,
+
+    #[arg(long, value_enum)]
+    /// Set or clear the DF bit of sent packets with IP_MTU_DISCOVER (Linux only)
+    pub df: Option<Df>,
+
+    #[arg(long)]
+    /// Send and receive through this network interface only, with SO_BINDTODEVICE (Linux only)
+    pub device: Option<String>,
+
+    #[arg(long)]
+    /// TCP congestion control algorithm, like cubic, reno or bbr, as far as the kernel has it (Linux only)
+    pub congestion: Option<String>,
+
+    #[arg(long)]
+    /// Disable Nagle's algorithm with TCP_NODELAY (Linux only)
+    pub nodelay: bool,
+
+    #[arg(long)]
+    /// Unsent bytes below which a TCP socket takes more writes, TCP_NOTSENT_LOWAT (Linux only)
+    pub notsent_lowat: Option<u32>,
+
+    #[arg(long, value_parser = clap::value_parser!(u16).range(88..))]
+    /// Clamp the TCP maximum segment size with TCP_MAXSEG, bytes (Linux only)
+    pub mss: Option<u16>,
+
+    #[arg(long)]
+    /// Size of the buffer coalescing the TCP client's writes, bytes, 0 writes every frame at once
+    pub write_buffer: Option<usize>,
+
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    /// SCTP streams to ask for in both directions, frames take them round robin [default: all on stream 0]
+    pub streams: Option<u16>,
+
+    #[arg(long)]
+    /// Deliver SCTP frames as they arrive instead of in the order of their stream
+    pub unordered: bool,
+
+    #[arg(long)]
+    /// PR-SCTP partial reliability of frames: ttl:MS abandons them after a lifetime, rtx:COUNT after retransmissions
+    pub pr_sctp: Option<PartialReliability>,
 }
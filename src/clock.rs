@@ -0,0 +1,80 @@
+/// Source of time for limiters and measurements
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// Time passed since `earlier`, zero if it is in the future
+    fn since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// Real monotonic time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Virtual time, which only moves when advanced
+///
+/// Clones share the time, so a test keeps one to drive the clock it hands out.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    origin: Instant,
+    /// Time advanced so far, ns
+    offset: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            offset: Arc::new(AtomicU64::new(0)),
+        }
+    }
+    pub fn advance(&self, by: Duration) {
+        self.offset
+            .fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+    }
+    /// Time advanced since creation
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.offset.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_is_shared() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        clock.clone().advance(Duration::from_millis(5));
+        assert_eq!(clock.since(start), Duration::from_millis(5));
+        assert_eq!(clock.since(start + Duration::from_secs(1)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,119 @@
+/// Module for adjusting a running sender
+use std::{
+    io::{BufRead, BufReader, Read},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use anyhow::Result;
+
+use crate::{rate::Bitrate, speed_controllers::KernelPacing};
+
+/// Command to the running sender
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Switch to a new target bitrate
+    Rate(Bitrate),
+    Pause,
+    Resume,
+    /// Switch to a new MTU, protocol headers included
+    Mtu(u16),
+    /// Print the session statistics
+    Stats,
+}
+
+/// Parses `rate <bitrate>`, `pause`, `resume`, `mtu <bytes>` or `stats`
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words[..] {
+            ["rate", rate] => Ok(Self::Rate(rate.parse()?)),
+            ["pause"] => Ok(Self::Pause),
+            ["resume"] => Ok(Self::Resume),
+            ["mtu", mtu] => mtu
+                .parse()
+                .map(Self::Mtu)
+                .map_err(|e| format!("invalid MTU `{mtu}`: {e}")),
+            ["stats"] => Ok(Self::Stats),
+            _ => Err(format!(
+                "unknown command `{s}`, expected rate <bitrate>, pause, resume, mtu <bytes> or stats"
+            )),
+        }
+    }
+}
+
+/// Commands for `sender_loop` along with what it needs to apply them
+pub struct Control {
+    pub commands: Receiver<Command>,
+    /// Bytes of the MTU taken by headers besides the record,
+    /// `None` if the record size has to stay the same
+    pub headers: Option<u16>,
+    /// Pacing to set up again on rate changes, while the kernel keeps up with it
+    pub pacing: KernelPacing,
+    /// Rate taken for `rate 0` where not limiting would flood the socket
+    pub unlimited: Option<Bitrate>,
+}
+
+/// Forwards commands read line by line from `input`, until it ends
+fn forward(input: impl Read, tx: &Sender<Command>) {
+    for line in BufReader::new(input).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(command) => {
+                if tx.send(command).is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// Reads commands from stdin for `-`, otherwise from clients of a Unix socket at `source`
+pub fn listen(source: &str) -> Result<Receiver<Command>> {
+    let (tx, rx) = mpsc::channel();
+    if source == "-" {
+        std::thread::spawn(move || forward(std::io::stdin(), &tx));
+        return Ok(rx);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+        // socket left over from a previous run
+        if std::fs::metadata(source).is_ok_and(|meta| meta.file_type().is_socket()) {
+            std::fs::remove_file(source)?;
+        }
+        let listener = UnixListener::bind(source)?;
+        println!("Listening for commands on {source}");
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                forward(stream, &tx);
+            }
+        });
+        Ok(rx)
+    }
+    #[cfg(not(unix))]
+    anyhow::bail!("Control sockets are not supported on this platform, use `-` for stdin")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!("rate 50M".parse(), Ok(Command::Rate(Bitrate(50_000_000))));
+        assert_eq!("  pause ".parse(), Ok(Command::Pause));
+        assert_eq!("mtu 1400".parse(), Ok(Command::Mtu(1400)));
+        assert!("mtu".parse::<Command>().is_err());
+        assert!("rate fast".parse::<Command>().is_err());
+    }
+}
@@ -0,0 +1,82 @@
+/// Module for results sent from the receiver back to the sender
+use crate::frames::FrameStatistics;
+
+/// Marks the beginning of every feedback message
+const MAGIC: [u8; 4] = *b"LTFB";
+
+/// Magic, `last` flag and 8 counters
+const FEEDBACK_LEN: usize = 4 + 1 + 8 * 8;
+
+/// Receiver's statistics for the current session
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Feedback {
+    /// Session has ended, no more feedback will follow
+    pub last: bool,
+    pub statistics: FrameStatistics,
+}
+
+impl Feedback {
+    pub fn new(last: bool, statistics: FrameStatistics) -> Self {
+        Self { last, statistics }
+    }
+
+    pub fn to_bytes(&self) -> [u8; FEEDBACK_LEN] {
+        let stats = &self.statistics;
+        let mut buf = [0; FEEDBACK_LEN];
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4] = self.last.into();
+        let counters = [
+            stats.session_id,
+            stats.valid,
+            stats.invalid,
+            stats.out_of_order,
+            stats.internally_bad,
+            stats.lost,
+            stats.resyncs,
+            stats.dropped,
+        ];
+        for (chunk, counter) in buf[5..].chunks_mut(8).zip(counters) {
+            chunk.copy_from_slice(&counter.to_be_bytes());
+        }
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != FEEDBACK_LEN || buf[..4] != MAGIC {
+            return None;
+        }
+        let mut counters = buf[5..]
+            .chunks(8)
+            .map(|chunk| u64::from_be_bytes(chunk.try_into().expect("8 byte chunks")));
+        let mut next = || counters.next().expect("8 counters");
+        Some(Self {
+            last: buf[4] != 0,
+            statistics: FrameStatistics {
+                session_id: next(),
+                valid: next(),
+                invalid: next(),
+                out_of_order: next(),
+                internally_bad: next(),
+                lost: next(),
+                resyncs: next(),
+                dropped: next(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feedback_roundtrip() {
+        let mut statistics = FrameStatistics::new(3);
+        statistics.valid = 1000;
+        statistics.lost = 5;
+        statistics.dropped = 2;
+        let feedback = Feedback::new(true, statistics);
+        assert_eq!(Feedback::from_bytes(&feedback.to_bytes()), Some(feedback));
+        assert_eq!(Feedback::from_bytes(&[0; FEEDBACK_LEN]), None);
+    }
+}
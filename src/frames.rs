@@ -1,12 +1,45 @@
-use cobs::{decode, encode, max_encoding_length};
 /// Module for frame generation and handling
-use std::time::{Duration, Instant};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    framing::Framing,
+    rate::Bitrate,
+};
 
 /// Maximum possible size of one frame (MTU=u16::MAX)
-const MAX_FRAME_SIZE: usize = 65536;
+pub(crate) const MAX_FRAME_SIZE: usize = 65536;
 
 /// Repeatable sequence to fill the frame data
-const SEQUNCE: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+pub(crate) const SEQUNCE: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// Frame data of the largest frame, shorter ones are its prefixes
+static PATTERN: [u8; MAX_FRAME_SIZE] = pattern();
+
+const fn pattern() -> [u8; MAX_FRAME_SIZE] {
+    let mut pattern = [0; MAX_FRAME_SIZE];
+    let mut i = 0;
+    while i < MAX_FRAME_SIZE {
+        pattern[i] = SEQUNCE[i % SEQUNCE.len()];
+        i += 1;
+    }
+    pattern
+}
+
+/// Counter of the frame with `payload` and whether the data after it is intact
+///
+/// `None` if the payload can't even fit the counter.
+pub(crate) fn check_payload(payload: &[u8]) -> Option<(u32, bool)> {
+    let (counter, data) = payload.split_first_chunk::<4>()?;
+    // one comparison of the whole data, which the standard library vectorises
+    Some((
+        u32::from_be_bytes(*counter),
+        PATTERN.get(..data.len()) == Some(data),
+    ))
+}
 
 /// Handles incoming frames and checks frame content for validity
 pub struct FrameHandler {
@@ -14,9 +47,10 @@ pub struct FrameHandler {
     statistics: FrameStatistics,
     buf: Box<[u8]>,
     speed_handler: SpeedMeasurer,
+    framing: Box<dyn Framing>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FrameStatistics {
     pub session_id: u64,
     pub valid: u64,
@@ -24,6 +58,9 @@ pub struct FrameStatistics {
     pub out_of_order: u64,
     pub internally_bad: u64,
     pub lost: u64,
+    pub resyncs: u64,
+    /// Lost frames which the receiving host dropped itself, its socket buffer being full
+    pub dropped: u64,
 }
 
 impl FrameStatistics {
@@ -35,17 +72,63 @@ impl FrameStatistics {
             out_of_order: 0,
             internally_bad: 0,
             lost: 0,
+            resyncs: 0,
+            dropped: 0,
+        }
+    }
+    /// Frames accounted for, both received and lost
+    pub fn total(&self) -> u64 {
+        self.valid + self.invalid + self.lost + self.internally_bad
+    }
+    /// Share of frames which were lost, percent, broken ones are counted apart
+    pub fn loss_percent(&self) -> f64 {
+        match self.total() {
+            0 => 0.,
+            total => self.lost as f64 / total as f64 * 100.,
+        }
+    }
+    /// Lost frames the receiving host didn't drop, so the network did
+    pub fn network_lost(&self) -> u64 {
+        self.lost.saturating_sub(self.dropped)
+    }
+}
+
+impl fmt::Display for FrameStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} valid, {} bad, {} mangled, {}/{} lost ({:.2}% loss)",
+            self.valid,
+            self.invalid,
+            self.internally_bad,
+            self.lost,
+            self.total(),
+            self.loss_percent()
+        )?;
+        if self.dropped > 0 {
+            write!(
+                f,
+                ", {} of them dropped by the receiving host, {} on the network",
+                self.dropped,
+                self.network_lost()
+            )?;
         }
+        Ok(())
     }
 }
 
 impl FrameHandler {
-    pub fn new() -> Self {
+    pub fn new(framing: Box<dyn Framing>) -> Self {
+        Self::with_session_id(framing, 1)
+    }
+    /// Handler whose first session is numbered `session_id`
+    pub fn with_session_id(framing: Box<dyn Framing>, session_id: u64) -> Self {
         Self {
             counter: u32::MAX,
-            statistics: FrameStatistics::new(1),
+            statistics: FrameStatistics::new(session_id),
             buf: vec![0; MAX_FRAME_SIZE].into_boxed_slice(),
             speed_handler: SpeedMeasurer::new(),
+            framing,
         }
     }
     pub fn reset(&mut self) {
@@ -53,26 +136,29 @@ impl FrameHandler {
         self.statistics = FrameStatistics::new(self.statistics.session_id + 1);
         self.speed_handler.reset();
     }
+    /// Record that the stream had to be resynchronized
+    pub fn resynced(&mut self) {
+        self.statistics.resyncs += 1;
+    }
+    /// Record that the receiving host dropped `count` frames before they reached the handler
+    pub fn dropped(&mut self, count: u64) {
+        self.statistics.dropped += count;
+    }
     /// Handle incoming frame
     ///
-    /// Takes a null-terminated slice representing the whole frame
-    pub fn handle(&mut self, frame: &[u8]) -> () {
-        self.speed_handler.handle(frame.len());
-        let frame = frame.strip_suffix(&[0]).unwrap_or(frame);
-        let frame = if let Ok(decoded_len) = decode(frame, &mut self.buf) {
-            &self.buf[..decoded_len]
-        } else {
+    /// Takes a slice representing the whole framed record
+    pub fn handle(&mut self, frame: &[u8]) {
+        self.handle_at(frame, None);
+    }
+    /// Same as `handle()`, with the frame's arrival time if the kernel stamped it
+    pub fn handle_at(&mut self, frame: &[u8], received: Option<Instant>) {
+        self.speed_handler.handle_at(frame.len(), received);
+        let Some((counter, intact)) = self.framing.verify(frame, &mut self.buf) else {
             self.statistics.invalid += 1;
             // eprintln!("Invalid because can't decode");
-            return ();
+            return;
         };
         self.counter = self.counter.wrapping_add(1);
-        let Ok(counter) = TryInto::<[u8; 4]>::try_into(&frame[0..4]) else {
-            self.statistics.invalid += 1;
-            // println!("Invalid because can't read counter");
-            return ();
-        };
-        let counter = u32::from_be_bytes(counter);
         match counter.cmp(&self.counter) {
             std::cmp::Ordering::Less => {
                 // println!("Behind");
@@ -84,28 +170,15 @@ impl FrameHandler {
             std::cmp::Ordering::Equal => {}
             std::cmp::Ordering::Greater => {
                 // println!("Ahead");
-                self.statistics.lost += Into::<u64>::into(counter - &self.counter);
+                self.statistics.lost += Into::<u64>::into(counter - self.counter);
                 self.counter = counter;
             }
         }
-        let data = &frame[4..];
-        for i in data.chunks(SEQUNCE.len()) {
-            if i.len() == SEQUNCE.len() {
-                if i != SEQUNCE {
-                    // println!("Improper chunk");
-                    break;
-                }
-            } else {
-                if &SEQUNCE[..i.len()] == i {
-                    self.statistics.valid += 1;
-                    return ();
-                } else {
-                    // println!("Improper end chunk");
-                    break;
-                }
-            }
+        if intact {
+            self.statistics.valid += 1;
+        } else {
+            self.statistics.internally_bad += 1;
         }
-        self.statistics.internally_bad += 1;
     }
     pub fn get_statistics(&self) -> Option<&FrameStatistics> {
         if self.counter == u32::MAX {
@@ -113,7 +186,7 @@ impl FrameHandler {
         }
         Some(&self.statistics)
     }
-    pub fn get_speeds(&self) -> (u64, u64) {
+    pub fn get_speeds(&self) -> (Bitrate, Bitrate) {
         self.speed_handler.get_speeds()
     }
 
@@ -130,71 +203,177 @@ pub struct FrameBuilder {
     buf: Box<[u8]>,
     start_time: Instant,
     total_send: u64,
-    cobs_encoded: Box<[u8]>,
+    encoded: Box<[u8]>,
+    framing: Box<dyn Framing>,
 }
 
 impl FrameBuilder {
-    /// Returns null-terminated slice presenting a cobs-encoded sequential frame
+    /// Returns slice presenting a framed sequential frame
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> &[u8] {
+        self.next_sized(self.encoded.len())
+    }
+    /// Same as `next()`, but the record is `size` bytes long
+    ///
+    /// `size` is clamped to fit the frame counter and MTU
+    pub fn next_sized(&mut self, size: usize) -> &[u8] {
         self.counter = self.counter.wrapping_add(1);
         let counter = &mut self.buf[0..4];
         counter.copy_from_slice(&self.counter.to_be_bytes());
-        self.total_send += self.buf.len() as u64;
-        let res = encode(&self.buf, &mut self.cobs_encoded);
-        self.cobs_encoded[res] = 0;
-        &self.cobs_encoded[..=res]
+        let payload = size
+            .saturating_sub(self.framing.overhead())
+            .clamp(4, self.buf.len());
+        let res = self.framing.encode(&self.buf[..payload], &mut self.encoded);
+        self.total_send += res as u64;
+        &self.encoded[..res]
     }
     /// Geterates payload for frame builed
-    pub fn new(mtu: u16) -> Self {
-        const COBS_OVERHEAD: u16 = 2;
-        let mut buf = vec![0_u8; Into::<usize>::into(mtu - COBS_OVERHEAD)].into_boxed_slice();
-        let buf2 = vec![0_u8; max_encoding_length(buf.len())].into_boxed_slice();
-
-        let data = &mut buf[4..];
-        let mut seq_iter = SEQUNCE.iter().cycle();
-        data.fill_with(|| *seq_iter.next().expect("Endless iterator"));
-        Self {
+    ///
+    /// `mtu` is the size of the whole record, including framing overhead
+    pub fn new(mtu: u16, framing: Box<dyn Framing>) -> Self {
+        let mut builder = Self {
             counter: u32::MAX,
-            buf: buf,
+            buf: Box::new([]),
             start_time: Instant::now(),
             total_send: 0,
-            cobs_encoded: buf2,
+            encoded: Box::new([]),
+            framing,
+        };
+        assert!(builder.resize(mtu), "MTU {mtu} can't fit the frame counter");
+        builder
+    }
+    /// Switches to records of `mtu` bytes, the sequence goes on
+    ///
+    /// Returns false and keeps the size if the record can't fit the counter
+    pub fn resize(&mut self, mtu: u16) -> bool {
+        let Some(payload) = usize::from(mtu)
+            .checked_sub(self.framing.overhead())
+            .filter(|payload| *payload >= 4)
+        else {
+            return false;
+        };
+        let mut buf = vec![0_u8; payload].into_boxed_slice();
+        buf[4..].copy_from_slice(&PATTERN[..payload - 4]);
+        self.buf = buf;
+        self.encoded = vec![0_u8; usize::from(mtu)].into_boxed_slice();
+        true
+    }
+    /// Takes back the last `count` records of `bytes` in total, which were never sent
+    ///
+    /// Their counters go to the next records, so the receiver sees no gap.
+    pub fn rewind(&mut self, count: u32, bytes: u64) {
+        self.counter = self.counter.wrapping_sub(count);
+        self.total_send -= bytes;
+    }
+    /// Average rate of records built since the start
+    pub fn get_avg_rate(&self) -> Bitrate {
+        let dur = self.start_time.elapsed().as_secs_f64();
+        if dur == 0. {
+            return Bitrate(0);
         }
+        Bitrate((self.total_send as f64 * 8. / dur) as u64)
     }
-    pub fn get_avg_kbps(&self) -> u64 {
-        let dur = self.start_time.elapsed().as_secs();
-        if dur == 0 {
-            return 0;
+}
+
+/// Frames collected to be handed to the socket at once
+pub struct FrameBatch {
+    data: Vec<u8>,
+    /// End of every frame in `data`
+    ends: Vec<usize>,
+    capacity: usize,
+    /// Allocation of the slices handed out by `with_frames`, empty in between
+    slices: Vec<&'static [u8]>,
+}
+
+/// Empties `slices` for slices of another lifetime, keeping the allocation
+fn recycle<'a>(mut slices: Vec<&[u8]>) -> Vec<&'a [u8]> {
+    slices.clear();
+    // collected in place, as nothing changes size
+    slices.into_iter().map(|_| unreachable!()).collect()
+}
+
+impl FrameBatch {
+    /// Batch of up to `capacity` frames
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            ends: Vec::with_capacity(capacity),
+            capacity: capacity.max(1),
+            slices: Vec::with_capacity(capacity),
         }
-        (self.total_send / 1024) * 8 / dur
+    }
+    pub fn push(&mut self, frame: &[u8]) {
+        self.data.extend_from_slice(frame);
+        self.ends.push(self.data.len());
+    }
+    pub fn is_full(&self) -> bool {
+        self.ends.len() >= self.capacity
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+    /// Calls `f` with the frames, without allocating once the batch has been full
+    pub fn with_frames<R>(&mut self, f: impl FnOnce(&[&[u8]]) -> R) -> R {
+        let mut frames = recycle(std::mem::take(&mut self.slices));
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        frames.extend(
+            starts
+                .zip(&self.ends)
+                .map(|(start, end)| &self.data[start..*end]),
+        );
+        let res = f(&frames);
+        self.slices = recycle(frames);
+        res
+    }
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.ends.clear();
     }
 }
 
-pub struct SpeedMeasurer {
+pub struct SpeedMeasurer<C: Clock = SystemClock> {
     session_start: Option<Instant>,
     session_received: usize,
     measure_start: Option<Instant>,
     measure_received: usize,
-    measure_speed: u64,
+    measure_speed: Bitrate,
     measure_latencies: Vec<u128>,
     prev_recv: Option<Instant>,
+    clock: C,
+}
+
+impl Default for SpeedMeasurer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SpeedMeasurer {
     pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl<C: Clock> SpeedMeasurer<C> {
+    pub fn with_clock(clock: C) -> Self {
         Self {
             session_start: None,
             session_received: 1,
             measure_start: None,
             measure_received: 1,
-            measure_speed: 0,
+            measure_speed: Bitrate(0),
             measure_latencies: vec![],
             prev_recv: None,
+            clock,
         }
     }
 
     pub fn handle(&mut self, len: usize) {
-        let time = Instant::now();
+        self.handle_at(len, None);
+    }
+    /// Same as `handle()` for data which arrived at `received`, now if unknown
+    pub fn handle_at(&mut self, len: usize, received: Option<Instant>) {
+        let time = received.unwrap_or_else(|| self.clock.now());
         let _session_start = self.session_start.get_or_insert(time);
         self.session_received += len;
         let measure_start = self.measure_start.get_or_insert(time);
@@ -212,35 +391,41 @@ impl SpeedMeasurer {
             self.prev_recv = Some(time);
         }
         self.measure_received += len;
-        if measure_start.elapsed() >= Duration::from_millis(1000) {
-            self.measure_speed = (self.measure_received as u128 * 8 / 1024 * 1000
-                / measure_start.elapsed().as_millis())
-            .try_into()
-            .unwrap();
-            *measure_start = Instant::now();
+        let measured = time.saturating_duration_since(*measure_start);
+        if measured >= Duration::from_millis(1000) {
+            self.measure_speed = Bitrate(
+                (self.measure_received as u128 * 8 * 1000 / measured.as_millis())
+                    .try_into()
+                    .unwrap(),
+            );
+            *measure_start = time;
             self.measure_received = 1;
         };
     }
-    pub fn get_speeds(&self) -> (u64, u64) {
+    pub fn get_speeds(&self) -> (Bitrate, Bitrate) {
         if self.session_start.is_none() {
-            return (0, 0);
+            return (Bitrate(0), Bitrate(0));
         }
-        let avg_session_speed = self.session_received as u128 * 8 / 1024 * 1000
-            / self.session_start.unwrap().elapsed().as_millis();
-        return (
-            avg_session_speed.try_into().unwrap_or(0),
+        let avg_session_speed = self.session_received as u128 * 8 * 1000
+            / self
+                .clock
+                .since(self.session_start.unwrap())
+                .as_millis()
+                .max(1);
+        (
+            Bitrate(avg_session_speed.try_into().unwrap_or(0)),
             self.measure_speed,
-        );
+        )
     }
     pub fn reset(&mut self) {
         self.session_start = None;
         self.measure_start = None;
         self.session_received = 1;
         self.measure_received = 1;
-        self.measure_speed = 0;
+        self.measure_speed = Bitrate(0);
     }
     pub fn get_latency(&self) -> u128 {
-        if self.measure_latencies.len() == 0 {
+        if self.measure_latencies.is_empty() {
             return 0;
         }
         let sum: u128 = self.measure_latencies.iter().sum();
@@ -252,26 +437,109 @@ impl SpeedMeasurer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        clock::MockClock,
+        framing::{CobsFraming, FixedFraming},
+    };
 
     #[test]
     #[ignore] // Takes a long time
     /// It's assumed in the code above, that COBS overhead will always be 2 for
     /// current SEQENCE, if the sequence changes, COBS might give a bigger overhead.
     fn test_cobs_overhead() {
-        let mut builder = FrameBuilder::new(1500);
+        let mut builder = FrameBuilder::new(1500, Box::new(CobsFraming::new()));
         for _ in (0..=u32::MAX).step_by(1) {
             assert_eq!(builder.next().len(), 1500)
         }
     }
 
+    #[test]
+    fn test_batch_keeps_frames() {
+        let mut builder = FrameBuilder::new(100, Box::new(CobsFraming::new()));
+        let mut batch = FrameBatch::new(3);
+        let mut handler = FrameHandler::new(Box::new(CobsFraming::new()));
+        batch.push(builder.next_sized(20));
+        batch.push(builder.next());
+        assert!(!batch.is_full());
+        batch.push(builder.next_sized(50));
+        assert!(batch.is_full());
+        let slices = batch.with_frames(|frames| {
+            assert_eq!(
+                frames.iter().map(|frame| frame.len()).collect::<Vec<_>>(),
+                [20, 100, 50]
+            );
+            frames.iter().for_each(|frame| handler.handle(frame));
+            frames.as_ptr() as usize
+        });
+        let stats = handler.get_statistics().unwrap();
+        assert_eq!((stats.valid, stats.lost), (3, 0));
+        batch.clear();
+        assert!(batch.is_empty());
+        // the next flush takes the same slices
+        batch.push(builder.next());
+        assert_eq!(batch.with_frames(|frames| frames.as_ptr() as usize), slices);
+    }
+
+    #[test]
+    fn test_rewind_reuses_counters() {
+        let mut builder = FrameBuilder::new(100, Box::new(CobsFraming::new()));
+        let mut handler = FrameHandler::new(Box::new(CobsFraming::new()));
+        handler.handle(builder.next());
+        let given_up = [builder.next().len(), builder.next().len()];
+        builder.rewind(2, given_up.iter().sum::<usize>() as u64);
+        handler.handle(builder.next());
+        let stats = handler.get_statistics().unwrap();
+        assert_eq!((stats.valid, stats.lost), (2, 0));
+    }
+
     #[test]
     fn test_frame_by_frame() {
-        let mut builder = FrameBuilder::new(1500);
-        let mut handler = FrameHandler::new();
+        let mut builder = FrameBuilder::new(1500, Box::new(CobsFraming::new()));
+        let mut handler = FrameHandler::new(Box::new(CobsFraming::new()));
         for i in 1..=5 {
             let frame = builder.next();
             handler.handle(frame);
             assert_eq!(handler.get_statistics().unwrap().valid, i)
         }
     }
+
+    #[test]
+    fn test_fixed_frame_aligned_payload() {
+        // 4 byte counter followed by exactly 3 sequence chunks
+        let framing = || Box::new(FixedFraming::new(52));
+        let mut builder = FrameBuilder::new(52, framing());
+        let mut handler = FrameHandler::new(framing());
+        handler.handle(builder.next());
+        let stats = handler.get_statistics().unwrap();
+        assert_eq!((stats.valid, stats.internally_bad), (1, 0));
+    }
+
+    #[test]
+    fn test_resize_keeps_sequence() {
+        let mut builder = FrameBuilder::new(1500, Box::new(CobsFraming::new()));
+        let mut handler = FrameHandler::new(Box::new(CobsFraming::new()));
+        handler.handle(builder.next());
+        assert!(!builder.resize(5));
+        assert!(builder.resize(100));
+        let frame = builder.next();
+        assert_eq!(frame.len(), 100);
+        handler.handle(frame);
+        let stats = handler.get_statistics().unwrap();
+        assert_eq!((stats.valid, stats.lost), (2, 0));
+    }
+
+    #[test]
+    fn test_speed_measurer() {
+        let clock = MockClock::new();
+        let mut measurer = SpeedMeasurer::with_clock(clock.clone());
+        // 1000 bytes every millisecond is 8 Mbit/s
+        for _ in 0..1500 {
+            measurer.handle(1000);
+            clock.advance(Duration::from_millis(1));
+        }
+        let (avg, instant) = measurer.get_speeds();
+        assert!(avg.0.abs_diff(8_000_000) < 80_000, "{avg}");
+        assert!(instant.0.abs_diff(8_000_000) < 80_000, "{instant}");
+        assert_eq!(measurer.get_latency(), 1000);
+    }
 }
@@ -0,0 +1,200 @@
+use std::io::{self, BufRead};
+
+use cobs::{decode, encode};
+
+use super::{Framing, Record};
+use crate::frames::{check_payload, MAX_FRAME_SIZE, SEQUNCE};
+
+/// Bytes encoding the frame counter, which the zero of the sequence cuts off
+const COUNTER_RECORD: usize = 5;
+
+/// Frame data as encoded after the counter
+///
+/// Every zero starting the sequence becomes a code pointing at the next one.
+static ENCODED_PATTERN: [u8; MAX_FRAME_SIZE] = encoded_pattern();
+
+const fn encoded_pattern() -> [u8; MAX_FRAME_SIZE] {
+    let mut pattern = [0; MAX_FRAME_SIZE];
+    let mut i = 0;
+    while i < MAX_FRAME_SIZE {
+        pattern[i] = match SEQUNCE[i % SEQUNCE.len()] {
+            0 => SEQUNCE.len() as u8,
+            byte => byte,
+        };
+        i += 1;
+    }
+    pattern
+}
+
+/// Zero-delimited COBS records
+///
+/// A single corrupted byte may merge or split records, such records are
+/// detected by checking the COBS structure and skipped.
+pub struct CobsFraming {}
+
+impl CobsFraming {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for CobsFraming {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks that code bytes of a zero-terminated COBS record land exactly on the delimiter
+fn is_well_formed(record: &[u8]) -> bool {
+    let end = record.len() - 1;
+    let mut pos = 0;
+    while pos < end {
+        match record[pos] {
+            0 => return false,
+            code => pos += usize::from(code),
+        }
+    }
+    pos == end && record[end] == 0
+}
+
+/// Counter of an intact frame, read from its record without decoding
+///
+/// Anything but an intact frame gives `None`, it's up to a full decode then.
+fn intact_counter(record: &[u8]) -> Option<u32> {
+    let record = record.strip_suffix(&[0])?;
+    let (head, body) = record.split_at_checked(COUNTER_RECORD)?;
+    if head.contains(&0) {
+        return None;
+    }
+    // zeros of the counter are where its codes point
+    let mut counter = [0; 4];
+    let mut pos = 0;
+    while pos < head.len() {
+        let end = pos + usize::from(head[pos]);
+        if end > head.len() {
+            return None;
+        }
+        counter[pos..end - 1].copy_from_slice(&head[pos + 1..end]);
+        pos = end;
+    }
+    if let Some(len) = body.len().checked_sub(1) {
+        // the last sequence may be cut short, and so its code
+        let last = len / SEQUNCE.len() * SEQUNCE.len();
+        let pattern = ENCODED_PATTERN.get(..body.len())?;
+        if body[..last] != pattern[..last]
+            || usize::from(body[last]) != body.len() - last
+            || body[last + 1..] != pattern[last + 1..]
+        {
+            return None;
+        }
+    }
+    Some(u32::from_be_bytes(counter))
+}
+
+impl Framing for CobsFraming {
+    /// It's assumed, that COBS overhead will always be 2 for the frame
+    /// sequence, since it has a zero byte in every chunk.
+    fn overhead(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, payload: &[u8], out: &mut [u8]) -> usize {
+        let len = encode(payload, out);
+        out[len] = 0;
+        len + 1
+    }
+
+    fn decode<'a>(&self, record: &'a [u8], out: &'a mut [u8]) -> Option<&'a [u8]> {
+        let record = record.strip_suffix(&[0]).unwrap_or(record);
+        let len = decode(record, out).ok()?;
+        Some(&out[..len])
+    }
+
+    /// Intact frames are compared with the encoded frame data as they are
+    fn verify(&self, record: &[u8], scratch: &mut [u8]) -> Option<(u32, bool)> {
+        if let Some(counter) = intact_counter(record) {
+            return Some((counter, true));
+        }
+        check_payload(self.decode(record, scratch)?)
+    }
+
+    fn terminator(&self) -> &'static [u8] {
+        &[0]
+    }
+
+    fn read_record(&self, stream: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<Record> {
+        let mut skipped = 0;
+        loop {
+            buf.clear();
+            if stream.read_until(0, buf)? == 0 || buf[..] == [0] || buf.last() != Some(&0) {
+                return Ok(Record::End);
+            }
+            if is_well_formed(buf) {
+                return Ok(Record::Data { skipped });
+            }
+            skipped += buf.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_in_place() {
+        let framing = CobsFraming::new();
+        let mut scratch = vec![0; MAX_FRAME_SIZE];
+        let mut buf = vec![0; 1502];
+        // counters with zeros in every position
+        for counter in [
+            0_u32,
+            0xff,
+            0xff00,
+            0xff0000,
+            0xff000000,
+            0x00ff00ff,
+            u32::MAX,
+        ] {
+            for size in [4, 5, 6, 19, 20, 21, 36, 1500] {
+                let payload: Vec<u8> = counter
+                    .to_be_bytes()
+                    .into_iter()
+                    .chain(SEQUNCE.into_iter().cycle().take(size - 4))
+                    .collect();
+                let len = framing.encode(&payload, &mut buf);
+                let record = &mut buf[..len];
+                assert_eq!(intact_counter(record), Some(counter));
+                // corrupted records end up as with a full decode
+                for i in 0..len {
+                    let byte = record[i];
+                    for corrupted in [0, 1, 16, byte ^ 0x80] {
+                        record[i] = corrupted;
+                        let decoded = framing.decode(record, &mut scratch).and_then(check_payload);
+                        assert_eq!(framing.verify(record, &mut scratch), decoded);
+                    }
+                    record[i] = byte;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_skips_broken_record() {
+        let framing = CobsFraming::new();
+        let payload = [1, 2, 3, 0, 4, 5];
+        let mut record = [0; 8];
+        let len = framing.encode(&payload, &mut record);
+
+        // Corrupt the first code byte, so it points past the delimiter
+        let mut stream = record[..len].to_vec();
+        stream[0] = 200;
+        stream.extend_from_slice(&record[..len]);
+        let mut stream = &stream[..];
+
+        let mut buf = vec![];
+        let res = framing.read_record(&mut stream, &mut buf).unwrap();
+        assert_eq!(res, Record::Data { skipped: len });
+        assert_eq!(buf, &record[..len]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, BufRead, ErrorKind};
+
+use super::{Framing, Record};
+
+/// Marks the beginning of every record
+const MAGIC: [u8; 4] = *b"LTRS";
+
+/// Size of magic and 32-bit length prefix
+const HEADER_LEN: usize = 8;
+
+/// Longest payload accepted from the stream, anything above means desync
+const MAX_PAYLOAD: usize = 65536;
+
+/// Records prefixed with magic and a 32-bit big-endian payload length
+///
+/// Zero-length record terminates the stream. When the header doesn't make
+/// sense, the stream is scanned byte by byte for the next magic.
+pub struct LengthFraming {}
+
+impl LengthFraming {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for LengthFraming {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns payload length if `header` is a valid record header
+fn parse_header(header: &[u8]) -> Option<usize> {
+    if header[..4] != MAGIC {
+        return None;
+    }
+    let len = u32::from_be_bytes(header[4..HEADER_LEN].try_into().ok()?) as usize;
+    (len <= MAX_PAYLOAD).then_some(len)
+}
+
+/// Maps EOF in the middle of a record to the end of stream
+fn read_exact_or_end(stream: &mut dyn BufRead, buf: &mut [u8]) -> io::Result<bool> {
+    match stream.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl Framing for LengthFraming {
+    fn overhead(&self) -> usize {
+        HEADER_LEN
+    }
+
+    fn encode(&self, payload: &[u8], out: &mut [u8]) -> usize {
+        out[..4].copy_from_slice(&MAGIC);
+        out[4..HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+        out[HEADER_LEN..HEADER_LEN + payload.len()].copy_from_slice(payload);
+        HEADER_LEN + payload.len()
+    }
+
+    fn decode<'a>(&self, record: &'a [u8], _out: &'a mut [u8]) -> Option<&'a [u8]> {
+        let len = parse_header(record.get(..HEADER_LEN)?)?;
+        record
+            .get(HEADER_LEN..)
+            .filter(|payload| payload.len() == len)
+    }
+
+    fn terminator(&self) -> &'static [u8] {
+        &[b'L', b'T', b'R', b'S', 0, 0, 0, 0]
+    }
+
+    fn read_record(&self, stream: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<Record> {
+        buf.clear();
+        buf.resize(HEADER_LEN, 0);
+        if !read_exact_or_end(stream, buf)? {
+            return Ok(Record::End);
+        }
+        let mut skipped = 0;
+        let len = loop {
+            if let Some(len) = parse_header(buf) {
+                break len;
+            }
+            buf.remove(0);
+            buf.push(0);
+            skipped += 1;
+            if !read_exact_or_end(stream, &mut buf[HEADER_LEN - 1..])? {
+                return Ok(Record::End);
+            }
+        };
+        if len == 0 {
+            return Ok(Record::End);
+        }
+        buf.resize(HEADER_LEN + len, 0);
+        if !read_exact_or_end(stream, &mut buf[HEADER_LEN..])? {
+            return Ok(Record::End);
+        }
+        Ok(Record::Data { skipped })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resync_on_garbage() {
+        let framing = LengthFraming::new();
+        let payload = [7; 32];
+        let mut record = [0; 40];
+        framing.encode(&payload, &mut record);
+
+        let mut stream = vec![b'L', b'T', 1, 2, 3];
+        stream.extend_from_slice(&record);
+        let mut stream = &stream[..];
+
+        let mut buf = vec![];
+        let res = framing.read_record(&mut stream, &mut buf).unwrap();
+        assert_eq!(res, Record::Data { skipped: 5 });
+        assert_eq!(buf, record);
+    }
+}
@@ -0,0 +1,84 @@
+/// Module for splitting a byte stream into records and back
+mod cobs;
+pub use self::cobs::CobsFraming;
+
+mod length;
+pub use length::LengthFraming;
+
+mod fixed;
+pub use fixed::FixedFraming;
+
+use std::io::{self, BufRead};
+
+use crate::frames::check_payload;
+
+/// Outcome of reading a single record from a stream
+#[derive(Debug, PartialEq, Eq)]
+pub enum Record {
+    /// A record was read, `skipped` bytes were discarded to find it
+    Data { skipped: usize },
+    /// Peer signalled the end of stream or closed the connection
+    End,
+}
+
+/// Record framing used by frame generation, verification and stream transports
+pub trait Framing: Send + Sync {
+    /// Amount of bytes the framing adds to every payload
+    fn overhead(&self) -> usize;
+
+    /// Frames `payload` into `out`, returns length of the resulting record
+    ///
+    /// `out` has to be at least `payload.len() + overhead()` long
+    fn encode(&self, payload: &[u8], out: &mut [u8]) -> usize;
+
+    /// Extracts payload from the `record`, using `out` as scratch space if needed
+    fn decode<'a>(&self, record: &'a [u8], out: &'a mut [u8]) -> Option<&'a [u8]>;
+
+    /// Checks the frame in `record`, returns its counter and whether its data is intact
+    ///
+    /// `None` if the record doesn't decode to a frame. Framings that copy
+    /// payloads when decoding can check intact frames in place instead.
+    fn verify(&self, record: &[u8], scratch: &mut [u8]) -> Option<(u32, bool)> {
+        check_payload(self.decode(record, scratch)?)
+    }
+
+    /// Record which tells the receiver that no more data will follow
+    fn terminator(&self) -> &'static [u8];
+
+    /// Reads the next record from `stream` into `buf`, resynchronizing if needed
+    fn read_record(&self, stream: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<Record>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(framing: &dyn Framing, payload: &[u8]) {
+        let mut record = vec![0; payload.len() + framing.overhead()];
+        let len = framing.encode(payload, &mut record);
+        assert_eq!(len, record.len());
+
+        let mut stream = record.clone();
+        stream.extend_from_slice(framing.terminator());
+        let mut stream = &stream[..];
+        let mut buf = vec![];
+        assert_eq!(
+            framing.read_record(&mut stream, &mut buf).unwrap(),
+            Record::Data { skipped: 0 }
+        );
+        let mut scratch = vec![0; buf.len()];
+        assert_eq!(framing.decode(&buf, &mut scratch).unwrap(), payload);
+        assert_eq!(
+            framing.read_record(&mut stream, &mut buf).unwrap(),
+            Record::End
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let payload: Vec<u8> = (0..100).collect();
+        roundtrip(&CobsFraming::new(), &payload);
+        roundtrip(&LengthFraming::new(), &payload);
+        roundtrip(&FixedFraming::new(payload.len()), &payload);
+    }
+}
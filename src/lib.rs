@@ -1,22 +1,167 @@
+pub mod accuracy;
 pub mod args;
+pub mod clock;
+pub mod control;
+pub mod feedback;
 pub mod frames;
+pub mod framing;
 pub mod protocols;
+pub mod rate;
+pub mod report;
 pub mod routines;
+pub mod search;
+pub mod snmp;
 pub mod speed_controllers;
+pub mod trace;
 
 use anyhow::Result;
 use args::*;
 use clap::Parser;
-use ctrlc;
-use protocols::{TcpReceiver, TcpSender, UdpReceiver, UdpSender};
+use control::Control;
+use framing::{CobsFraming, FixedFraming, Framing, LengthFraming};
+use protocols::{
+    SctpOptions, Sender, SocketOptions, TcpOptions, TcpReceiver, TcpSender, UdpReceiver, UdpSender,
+};
+#[cfg(target_os = "linux")]
+use protocols::{SctpReceiver, SctpSender};
+use rate::{Bitrate, Rate};
 use routines::*;
-use speed_controllers::UnLimiter;
+use search::{print_table, throughput_search, SearchParams, ETHERNET_OVERHEAD};
+use snmp::HostErrors;
+use speed_controllers::{
+    AimdLimiter, KernelLimiter, KernelPacing, ProfileLimiter, RandomLimiter, TokenBucketLimiter,
+    TraceLimiter, UnLimiter,
+};
 #[allow(unused_imports)]
 use speed_controllers::{BurstLimiter, Limiter, OverTimeLimiter, StaticLimiter};
-use std::sync::mpsc;
+use std::{sync::mpsc, time::Duration};
+use trace::Trace;
+
+/// IP + UDP header size
+const UDP_HEADERS: u16 = 28;
+
+/// IP + TCP header size
+const TCP_HEADERS: u16 = 40;
+
+/// IP + SCTP common header + DATA chunk header size
+const SCTP_HEADERS: u16 = 48;
+
+/// Rate of UDP burst and token bucket limiters asked not to limit, which would flood the socket
+const UDP_UNLIMITED: Bitrate = Bitrate(1_000_000);
+
+/// Ethernet header, counted by the qdisc pacing the frames
+const ETHERNET_HEADER: u16 = 14;
+
+/// Size of the protocol headers of every frame
+fn headers(proto: &Proto) -> u16 {
+    match proto {
+        Proto::UDP => UDP_HEADERS,
+        // options of MPTCP vary by segment, they are left out as TCP ones are
+        Proto::TCP | Proto::MPTCP => TCP_HEADERS,
+        Proto::SCTP => SCTP_HEADERS,
+    }
+}
+
+/// Size of frames which fit into `mtu` with the protocol headers
+fn frame_size(proto: &Proto, mtu: u16, format: &FrameFormat) -> Result<u16> {
+    let headers = headers(proto);
+    // frame counter, that has to fit along with framing
+    let counter = 4;
+    let overhead = framing(format, 0).overhead() as u16;
+    mtu.checked_sub(headers)
+        .filter(|size| *size >= counter + overhead)
+        .ok_or_else(|| anyhow::anyhow!("MTU {mtu} is too small for the protocol and framing"))
+}
+
+/// Prints receiver's final statistics of the session
+fn print_feedback(result: &SenderResult) {
+    match &result.feedback {
+        Some(feedback) => println!("Server results: {}", feedback.statistics),
+        None => println!("No results from the server"),
+    }
+}
+
+/// Offloads pacing of `socket` at `rate` to the kernel
+///
+/// Keeps user space `limiter` as a fallback, alone if the kernel refuses.
+fn kernel_pacing(
+    socket: &mut impl Sender,
+    pacing: &KernelPacing,
+    rate: Rate,
+    limiter: Box<dyn Limiter>,
+) -> Box<dyn Limiter> {
+    if rate.is_unlimited() || matches!(pacing.pacing, Pacing::USER) {
+        return limiter;
+    }
+    match pacing.apply(socket, &rate) {
+        Ok(()) => Box::new(KernelLimiter::new(rate, limiter)),
+        Err(e) => {
+            eprintln!("Kernel pacing is unavailable, pacing in user space: {e}");
+            limiter
+        }
+    }
+}
+
+/// Socket options of every transport from the arguments
+fn socket_options(args: &Args) -> SocketOptions {
+    SocketOptions {
+        send_buffer: args.sndbuf,
+        recv_buffer: args.rcvbuf,
+        tos: SocketOptions::tos(args.dscp, args.ecn.as_ref().map(Ecn::codepoint)),
+        ttl: args.ttl,
+        multicast_ttl: Some(args.multicast_ttl),
+        dont_fragment: args.df.as_ref().map(|df| matches!(df, Df::SET)),
+        device: args.device.clone(),
+    }
+}
+
+/// TCP options of the command line
+fn tcp_options(args: &Args) -> TcpOptions {
+    TcpOptions {
+        congestion: args.congestion.clone(),
+        nodelay: args.nodelay,
+        notsent_lowat: args.notsent_lowat,
+        mss: args.mss,
+        write_buffer: args.write_buffer,
+        multipath: matches!(args.proto, Proto::MPTCP),
+    }
+}
+
+/// SCTP options of the command line
+fn sctp_options(args: &Args) -> SctpOptions {
+    SctpOptions {
+        streams: args.streams,
+        unordered: args.unordered,
+        reliability: args.pr_sctp,
+    }
+}
+
+/// Creates framing for records of `size` bytes
+fn framing(format: &FrameFormat, size: u16) -> Box<dyn Framing> {
+    match format {
+        FrameFormat::COBS => Box::new(CobsFraming::new()),
+        FrameFormat::LENGTH => Box::new(LengthFraming::new()),
+        FrameFormat::FIXED => Box::new(FixedFraming::new(size.into())),
+    }
+}
 
 pub fn entrypoint() -> Result<()> {
     let args = Args::parse();
+    let options = socket_options(&args);
+    let tcp = tcp_options(&args);
+    anyhow::ensure!(
+        matches!(args.proto, Proto::TCP | Proto::MPTCP) || tcp == TcpOptions::default(),
+        "TCP options need --proto tcp or mptcp"
+    );
+    let sctp = sctp_options(&args);
+    anyhow::ensure!(
+        matches!(args.proto, Proto::SCTP) || sctp == SctpOptions::default(),
+        "SCTP options need --proto sctp"
+    );
+    anyhow::ensure!(
+        !matches!((&args.proto, &args.io), (Proto::SCTP, IoBackend::URING)),
+        "SCTP doesn't work with io_uring"
+    );
     let (tx, rx) = mpsc::channel::<()>();
     let _ = ctrlc::set_handler(move || {
         let _ = tx.send(());
@@ -27,40 +172,379 @@ pub fn entrypoint() -> Result<()> {
             addr,
             port,
             interval,
-        } => match args.proto {
-            Proto::UDP => reciever_loop(UdpReceiver::new(addr, port, args.bind)?, interval, rx),
-            Proto::TCP => reciever_loop(TcpReceiver::new(addr, port)?, interval, rx),
-        },
+            mtu,
+            feedback,
+            gro,
+            workers,
+            cpus,
+            host_drops,
+        } => {
+            let frame_size = frame_size(&args.proto, mtu, &args.framing)?;
+            let feedback = (feedback != 0).then(|| Duration::from_millis(feedback));
+            anyhow::ensure!(
+                matches!(args.proto, Proto::UDP) || !gro,
+                "GRO is for UDP only"
+            );
+            let uring = matches!(args.io, IoBackend::URING);
+            anyhow::ensure!(!(uring && gro), "GRO doesn't work with io_uring");
+            let sharded = workers > 1 || !cpus.is_empty();
+            anyhow::ensure!(
+                matches!(args.proto, Proto::UDP) || !sharded,
+                "Receiving workers are for UDP only"
+            );
+            anyhow::ensure!(
+                !(uring && sharded),
+                "Receiving workers don't work with io_uring"
+            );
+            anyhow::ensure!(
+                matches!(args.proto, Proto::UDP) || !host_drops,
+                "Host drops are counted for UDP only"
+            );
+            let host = match host_drops {
+                true => {
+                    Some(HostErrors::new().map_err(|e| anyhow::anyhow!("/proc/net/snmp: {e}"))?)
+                }
+                false => None,
+            };
+            match args.proto {
+                Proto::UDP if sharded => sharded_receiver_loop(
+                    workers.into(),
+                    || {
+                        let mut socket = UdpReceiver::new_shared(
+                            addr,
+                            port,
+                            args.bind,
+                            args.batch.into(),
+                            mtu,
+                            &options,
+                        )?;
+                        if gro {
+                            if let Err(e) = socket.set_gro() {
+                                eprintln!(
+                                    "GRO is unavailable, receiving datagrams one by one: {e}"
+                                );
+                            }
+                        }
+                        Ok(socket)
+                    },
+                    &cpus,
+                    || framing(&args.framing, frame_size),
+                    interval,
+                    feedback,
+                    host,
+                    rx,
+                ),
+                Proto::UDP => {
+                    let mut socket =
+                        UdpReceiver::new(addr, port, args.bind, args.batch.into(), mtu, &options)?;
+                    if uring {
+                        socket.set_uring()?;
+                    }
+                    if gro {
+                        if let Err(e) = socket.set_gro() {
+                            eprintln!("GRO is unavailable, receiving datagrams one by one: {e}");
+                        }
+                    }
+                    reciever_loop(
+                        socket,
+                        framing(&args.framing, frame_size),
+                        interval,
+                        feedback,
+                        host,
+                        rx,
+                    )
+                }
+                Proto::TCP | Proto::MPTCP => {
+                    let records = framing(&args.framing, frame_size);
+                    let mut socket = TcpReceiver::new(addr, port, records, &options, &tcp)?;
+                    if uring {
+                        socket.set_uring()?;
+                    }
+                    reciever_loop(
+                        socket,
+                        framing(&args.framing, frame_size),
+                        interval,
+                        feedback,
+                        None,
+                        rx,
+                    )
+                }
+                #[cfg(target_os = "linux")]
+                Proto::SCTP => {
+                    let socket = SctpReceiver::new(addr, port, &options, &sctp)?;
+                    reciever_loop(
+                        socket,
+                        framing(&args.framing, frame_size),
+                        interval,
+                        feedback,
+                        None,
+                        rx,
+                    )
+                }
+                #[cfg(not(target_os = "linux"))]
+                Proto::SCTP => anyhow::bail!("SCTP is Linux only"),
+            }
+        }
         Commands::Client {
             addr,
             port,
             bandwidth,
+            pps,
+            profile,
             mtu,
-        } => match (args.proto, bandwidth) {
-            (Proto::UDP, 0) => sender_loop(
-                UdpSender::new(addr, port, args.bind)?,
-                mtu - 28,
-                BurstLimiter::new(1000, mtu, true),
-                rx,
-            ),
-            (Proto::TCP, 0) => sender_loop(
-                TcpSender::new(addr, port, args.bind)?,
-                mtu - 40,
-                UnLimiter::new(),
-                rx,
-            ),
-            (Proto::UDP, bandwidth) => sender_loop(
-                UdpSender::new(addr, port, args.bind)?,
-                mtu - 28,
-                BurstLimiter::new(bandwidth, mtu, true),
-                rx,
-            ),
-            (Proto::TCP, bandwidth) => sender_loop(
-                TcpSender::new(addr, port, args.bind)?,
-                mtu - 40,
-                BurstLimiter::new(bandwidth, mtu, false),
-                rx,
-            ),
-        },
+            limiter,
+            pacing,
+            bucket,
+            increase,
+            decrease,
+            traffic,
+            seed,
+            trace,
+            looped,
+            control,
+            gso,
+            tx_timestamps,
+        } => {
+            let frame_size = frame_size(&args.proto, mtu, &args.framing)?;
+            let rate = match pps {
+                Some(pps) => Rate::packets(pps, frame_size),
+                None => Rate::bandwidth(bandwidth, frame_size),
+            };
+            let floods = matches!(
+                (&args.proto, &limiter),
+                (Proto::UDP, LimiterKind::BURST | LimiterKind::TOKEN)
+            );
+            let rate = if rate.is_unlimited() && floods {
+                Rate::bandwidth(UDP_UNLIMITED, frame_size)
+            } else {
+                rate
+            };
+            anyhow::ensure!(
+                profile.is_none() || matches!(limiter, LimiterKind::BURST),
+                "A bandwidth profile has its own limiter, it can't be combined with --limiter"
+            );
+            anyhow::ensure!(
+                matches!(pacing, Pacing::USER)
+                    || (profile.is_none() && matches!(limiter, LimiterKind::BURST)),
+                "Kernel pacing only works with the burst limiter"
+            );
+            anyhow::ensure!(
+                matches!(args.proto, Proto::UDP) || !gso,
+                "GSO is for UDP only"
+            );
+            let uring = matches!(args.io, IoBackend::URING);
+            anyhow::ensure!(!(uring && gso), "GSO doesn't work with io_uring");
+            anyhow::ensure!(
+                matches!(args.proto, Proto::UDP) || !tx_timestamps,
+                "Transmit timestamps are for UDP only"
+            );
+            if profile.is_none() && !matches!(limiter, LimiterKind::TRACE) {
+                println!("Target rate: {rate}");
+            }
+            let limiter: Box<dyn Limiter> = match (limiter, &args.proto, profile) {
+                (_, _, Some(profile)) => Box::new(ProfileLimiter::new(profile, frame_size)),
+                (
+                    LimiterKind::BURST | LimiterKind::TOKEN,
+                    Proto::TCP | Proto::MPTCP | Proto::SCTP,
+                    None,
+                ) if rate.is_unlimited() => Box::new(UnLimiter::new()),
+                (LimiterKind::BURST, proto, None) => {
+                    Box::new(BurstLimiter::new(rate, matches!(proto, Proto::UDP)))
+                }
+                (LimiterKind::TOKEN, _, None) => Box::new(TokenBucketLimiter::new(rate, bucket)),
+                (LimiterKind::AIMD, _, None) => Box::new(AimdLimiter::new(
+                    rate.bitrate(),
+                    increase,
+                    decrease,
+                    frame_size,
+                )),
+                (LimiterKind::RANDOM, _, None) => {
+                    let seed = seed.unwrap_or_else(rand::random);
+                    println!("Random limiter seed: {seed}");
+                    Box::new(RandomLimiter::new(rate, traffic, seed))
+                }
+                (LimiterKind::TRACE, proto, None) => {
+                    anyhow::ensure!(
+                        !matches!(args.framing, FrameFormat::FIXED),
+                        "Trace replay needs variable frame sizes, fixed framing can't carry them"
+                    );
+                    let trace = Trace::from_file(&trace.expect("required by clap"))?;
+                    let min = 4 + framing(&args.framing, 0).overhead() as u16;
+                    Box::new(TraceLimiter::new(
+                        &trace,
+                        headers(proto),
+                        min,
+                        frame_size,
+                        looped,
+                    ))
+                }
+            };
+            let kernel = KernelPacing {
+                pacing,
+                overhead: headers(&args.proto) + ETHERNET_HEADER,
+            };
+            let control = match control {
+                Some(source) => Some(Control {
+                    commands: control::listen(&source)?,
+                    // receiver expects records of the same size
+                    headers: (!matches!(args.framing, FrameFormat::FIXED))
+                        .then(|| headers(&args.proto)),
+                    pacing: kernel.clone(),
+                    unlimited: floods.then_some(UDP_UNLIMITED),
+                }),
+                None => None,
+            };
+            let result = match args.proto {
+                Proto::UDP => {
+                    let mut socket =
+                        UdpSender::new(addr, port, args.bind, args.batch.into(), &options)?;
+                    if uring {
+                        socket.set_uring()?;
+                    }
+                    if gso {
+                        if let Err(e) = socket.set_gso() {
+                            eprintln!("GSO is unavailable, sending datagrams one by one: {e}");
+                        }
+                    }
+                    if tx_timestamps {
+                        if let Err(e) = socket.set_tx_timestamps() {
+                            eprintln!("Transmit timestamps are unavailable: {e}");
+                        }
+                    }
+                    let limiter = kernel_pacing(&mut socket, &kernel, rate, limiter);
+                    sender_loop(
+                        socket,
+                        frame_size,
+                        framing(&args.framing, frame_size),
+                        limiter,
+                        None,
+                        &rx,
+                        control,
+                    )
+                }
+                Proto::TCP | Proto::MPTCP => {
+                    let mut socket = TcpSender::new(
+                        addr,
+                        port,
+                        args.bind,
+                        framing(&args.framing, frame_size),
+                        &options,
+                        &tcp,
+                    )?;
+                    if uring {
+                        socket.set_uring(args.batch.into())?;
+                    }
+                    let limiter = kernel_pacing(&mut socket, &kernel, rate, limiter);
+                    sender_loop(
+                        socket,
+                        frame_size,
+                        framing(&args.framing, frame_size),
+                        limiter,
+                        None,
+                        &rx,
+                        control,
+                    )
+                }
+                #[cfg(target_os = "linux")]
+                Proto::SCTP => {
+                    let mut socket = SctpSender::new(addr, port, args.bind, &options, &sctp)?;
+                    let limiter = kernel_pacing(&mut socket, &kernel, rate, limiter);
+                    sender_loop(
+                        socket,
+                        frame_size,
+                        framing(&args.framing, frame_size),
+                        limiter,
+                        None,
+                        &rx,
+                        control,
+                    )
+                }
+                #[cfg(not(target_os = "linux"))]
+                Proto::SCTP => anyhow::bail!("SCTP is Linux only"),
+            }?;
+            print_feedback(&result);
+            Ok(())
+        }
+        Commands::Search {
+            addr,
+            port,
+            sizes,
+            max,
+            duration,
+            threshold,
+            resolution,
+        } => {
+            let params = SearchParams {
+                frame_sizes: sizes,
+                max,
+                threshold,
+                resolution,
+                gap: Duration::from_secs(1),
+            };
+            let duration = Some(Duration::from_secs(duration));
+            let results = throughput_search(&params, |size, fps| {
+                let mtu = size.saturating_sub(ETHERNET_OVERHEAD);
+                let frame_size = frame_size(&args.proto, mtu, &args.framing)?;
+                let rate = Rate::packets(fps.max(1), frame_size);
+                let limiter = BurstLimiter::new(rate, matches!(args.proto, Proto::UDP));
+                let uring = matches!(args.io, IoBackend::URING);
+                let result = match args.proto {
+                    Proto::UDP => {
+                        let mut socket =
+                            UdpSender::new(addr, port, args.bind, args.batch.into(), &options)?;
+                        if uring {
+                            socket.set_uring()?;
+                        }
+                        sender_loop(
+                            socket,
+                            frame_size,
+                            framing(&args.framing, frame_size),
+                            limiter,
+                            duration,
+                            &rx,
+                            None,
+                        )
+                    }
+                    Proto::TCP | Proto::MPTCP => {
+                        let mut socket = TcpSender::new(
+                            addr,
+                            port,
+                            args.bind,
+                            framing(&args.framing, frame_size),
+                            &options,
+                            &tcp,
+                        )?;
+                        if uring {
+                            socket.set_uring(args.batch.into())?;
+                        }
+                        sender_loop(
+                            socket,
+                            frame_size,
+                            framing(&args.framing, frame_size),
+                            limiter,
+                            duration,
+                            &rx,
+                            None,
+                        )
+                    }
+                    #[cfg(target_os = "linux")]
+                    Proto::SCTP => sender_loop(
+                        SctpSender::new(addr, port, args.bind, &options, &sctp)?,
+                        frame_size,
+                        framing(&args.framing, frame_size),
+                        limiter,
+                        duration,
+                        &rx,
+                        None,
+                    ),
+                    #[cfg(not(target_os = "linux"))]
+                    Proto::SCTP => anyhow::bail!("SCTP is Linux only"),
+                }?;
+                print_feedback(&result);
+                Ok(result)
+            })?;
+            print_table(&results);
+            Ok(())
+        }
     }
 }
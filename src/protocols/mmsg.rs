@@ -0,0 +1,276 @@
+//! Batched datagram I/O with `sendmmsg` and `recvmmsg`, Linux only
+//!
+//! Batches can also use UDP segmentation offload: a run of equally sized
+//! frames goes out as one buffer which the kernel splits with `UDP_SEGMENT`,
+//! and with `UDP_GRO` received datagrams come coalesced along with their size.
+use std::{
+    io,
+    mem::{size_of, zeroed},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    os::fd::AsRawFd,
+    time::SystemTime,
+};
+
+use super::{sockopt::setsockopt, timestamps};
+
+/// Most datagrams the kernel takes in one call, UIO_MAXIOV
+pub const MAX_BATCH: usize = 1024;
+
+/// Most segments of one GSO buffer, UDP_MAX_SEGMENTS of older kernels
+const MAX_SEGMENTS: usize = 64;
+
+/// Largest UDP payload over IPv4
+const MAX_PAYLOAD: usize = 65507;
+
+/// Room for the GRO segment size and drop count, an integer each, and the arrival timespec,
+/// aligned for cmsghdr
+type Control = [u64; 10];
+
+/// Datagram received by `recv_batch`
+pub struct Datagram {
+    pub len: usize,
+    pub addr: SocketAddr,
+    /// Size of the datagrams coalesced into this one by GRO
+    pub segment: Option<usize>,
+    /// Datagrams the socket has dropped so far, wrapping, once there are any
+    pub drops: Option<u32>,
+    /// Arrival at the socket, if it is stamped
+    pub received: Option<SystemTime>,
+    /// Control messages didn't fit, the fields above may be missing
+    pub truncated: bool,
+}
+
+/// Checks that the kernel segments UDP buffers for `socket`
+pub fn probe_gso(socket: &impl AsRawFd) -> io::Result<()> {
+    // 0 leaves segmentation to control messages
+    setsockopt(socket.as_raw_fd(), libc::SOL_UDP, libc::UDP_SEGMENT, &0_i32)
+}
+
+/// Lets the kernel coalesce datagrams received on `socket`
+pub fn enable_gro(socket: &impl AsRawFd) -> io::Result<()> {
+    setsockopt(socket.as_raw_fd(), libc::SOL_UDP, libc::UDP_GRO, &1_i32)
+}
+
+/// Has datagrams received on `socket` carry the count of those it dropped with `SO_RXQ_OVFL`
+pub fn enable_drop_counter(socket: &impl AsRawFd) -> io::Result<()> {
+    setsockopt(
+        socket.as_raw_fd(),
+        libc::SOL_SOCKET,
+        libc::SO_RXQ_OVFL,
+        &1_i32,
+    )
+}
+
+/// Splits `frames` into runs of frames going out as one datagram, returns their lengths
+///
+/// With `gso` a run is consecutive frames of the same size which fit
+/// a single buffer, otherwise every frame is a run of its own.
+fn runs(frames: &[&[u8]], gso: bool) -> Vec<usize> {
+    let mut runs: Vec<usize> = Vec::new();
+    let mut size = 0;
+    for (i, frame) in frames.iter().enumerate() {
+        let joins = match runs.last() {
+            Some(&run) if gso => {
+                frame.len() == frames[i - 1].len()
+                    && run < MAX_SEGMENTS
+                    && size + frame.len() <= MAX_PAYLOAD
+            }
+            _ => false,
+        };
+        if joins {
+            *runs.last_mut().expect("checked above") += 1;
+            size += frame.len();
+        } else {
+            runs.push(1);
+            size = frame.len();
+        }
+    }
+    runs
+}
+
+/// Sends `frames` as separate datagrams, returns how many were sent
+///
+/// With `gso` runs of them are handed over as single buffers, which is
+/// cheaper still when the frames lie back to back in memory.
+pub fn send_batch(socket: &impl AsRawFd, frames: &[&[u8]], gso: bool) -> io::Result<usize> {
+    let frames = &frames[..frames.len().min(MAX_BATCH)];
+    let runs = runs(frames, gso);
+    let mut iovecs: Vec<libc::iovec> = Vec::with_capacity(frames.len());
+    // first iovec, count of them and segment size of every run
+    let mut spans = Vec::with_capacity(runs.len());
+    let mut rest = frames;
+    for run in &runs {
+        let (head, tail) = rest.split_at(*run);
+        rest = tail;
+        let first = iovecs.len();
+        for frame in head {
+            let end = iovecs[first..]
+                .last()
+                .map(|iov| (iov.iov_base as *const u8).wrapping_add(iov.iov_len));
+            if end == Some(frame.as_ptr()) {
+                iovecs.last_mut().expect("checked above").iov_len += frame.len();
+            } else {
+                iovecs.push(libc::iovec {
+                    iov_base: frame.as_ptr() as *mut libc::c_void,
+                    iov_len: frame.len(),
+                });
+            }
+        }
+        spans.push((
+            first,
+            iovecs.len() - first,
+            (*run > 1).then_some(head[0].len()),
+        ));
+    }
+    let mut controls: Vec<Control> = vec![Control::default(); runs.len()];
+    let mut messages: Vec<libc::mmsghdr> = spans
+        .iter()
+        .zip(controls.iter_mut())
+        .map(|((first, count, segment), control)| {
+            let mut message: libc::mmsghdr = unsafe { zeroed() };
+            let hdr = &mut message.msg_hdr;
+            hdr.msg_iov = iovecs[*first..].as_mut_ptr();
+            hdr.msg_iovlen = *count as _;
+            if let Some(segment) = segment {
+                hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                hdr.msg_controllen = unsafe { libc::CMSG_SPACE(size_of::<u16>() as u32) } as _;
+                unsafe {
+                    let cmsg = libc::CMSG_FIRSTHDR(hdr);
+                    (*cmsg).cmsg_level = libc::SOL_UDP;
+                    (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                    (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u16>() as u32) as _;
+                    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, *segment as u16);
+                }
+            }
+            message
+        })
+        .collect();
+    let res = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            messages.as_mut_ptr(),
+            messages.len() as libc::c_uint,
+            0,
+        )
+    };
+    match res {
+        -1 => Err(io::Error::last_os_error()),
+        sent => Ok(runs[..sent as usize].iter().sum()),
+    }
+}
+
+/// Fills in what the control messages of `hdr` tell about `datagram`
+fn read_control(hdr: &libc::msghdr, datagram: &mut Datagram) {
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(hdr) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        let data = unsafe { libc::CMSG_DATA(cmsg) };
+        match (header.cmsg_level, header.cmsg_type) {
+            (libc::SOL_UDP, libc::UDP_GRO) => {
+                let segment = unsafe { std::ptr::read_unaligned(data as *const i32) };
+                datagram.segment = usize::try_from(segment).ok().filter(|segment| *segment > 0);
+            }
+            (libc::SOL_SOCKET, libc::SO_RXQ_OVFL) => {
+                datagram.drops = Some(unsafe { std::ptr::read_unaligned(data as *const u32) });
+            }
+            (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                let ts = unsafe { std::ptr::read_unaligned(data as *const libc::timespec) };
+                datagram.received = Some(timestamps::wall_time(&ts));
+            }
+            _ => {}
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(hdr, cmsg) };
+    }
+}
+
+/// Receives up to `buf.len() / stride` datagrams, each into its own `stride` long slot
+///
+/// Blocks for the first datagram only.
+pub fn recv_batch(
+    socket: &impl AsRawFd,
+    buf: &mut [u8],
+    stride: usize,
+) -> io::Result<Vec<Datagram>> {
+    let count = (buf.len() / stride).min(MAX_BATCH);
+    let mut addrs: Vec<libc::sockaddr_in> = vec![unsafe { zeroed() }; count];
+    let mut controls: Vec<Control> = vec![Control::default(); count];
+    let mut iovecs: Vec<libc::iovec> = buf
+        .chunks_exact_mut(stride)
+        .take(count)
+        .map(|slot| libc::iovec {
+            iov_base: slot.as_mut_ptr() as *mut libc::c_void,
+            iov_len: slot.len(),
+        })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(addrs.iter_mut())
+        .zip(controls.iter_mut())
+        .map(|((iov, addr), control)| {
+            let mut message: libc::mmsghdr = unsafe { zeroed() };
+            let hdr = &mut message.msg_hdr;
+            hdr.msg_iov = iov;
+            hdr.msg_iovlen = 1;
+            hdr.msg_name = addr as *mut libc::sockaddr_in as *mut libc::c_void;
+            hdr.msg_namelen = size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = size_of::<Control>() as _;
+            message
+        })
+        .collect();
+    let res = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            messages.as_mut_ptr(),
+            count as libc::c_uint,
+            libc::MSG_WAITFORONE,
+            std::ptr::null_mut(),
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(messages[..res as usize]
+        .iter()
+        .zip(&addrs)
+        .map(|(message, addr)| {
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            let port = u16::from_be(addr.sin_port);
+            let mut datagram = Datagram {
+                len: message.msg_len as usize,
+                addr: SocketAddrV4::new(ip, port).into(),
+                segment: None,
+                drops: None,
+                received: None,
+                truncated: message.msg_hdr.msg_flags & libc::MSG_CTRUNC != 0,
+            };
+            read_control(&message.msg_hdr, &mut datagram);
+            datagram
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs() {
+        let small = [0_u8; 100];
+        let large = [0_u8; 1472];
+        let frames = [&small[..], &small, &large, &small];
+        assert_eq!(runs(&frames, false), [1, 1, 1, 1]);
+        assert_eq!(runs(&frames, true), [2, 1, 1]);
+        // buffers stay within the segment count and UDP payload limits
+        assert_eq!(runs(&[&small[..]; 100], true), [64, 36]);
+        assert_eq!(runs(&[&large[..]; 50], true), [44, 6]);
+    }
+
+    #[test]
+    fn test_control() {
+        // UDP_GRO, SO_RXQ_OVFL and SCM_TIMESTAMPNS together
+        let int = unsafe { libc::CMSG_SPACE(size_of::<libc::c_int>() as u32) } as usize;
+        let stamp = unsafe { libc::CMSG_SPACE(size_of::<libc::timespec>() as u32) } as usize;
+        assert!(2 * int + stamp <= size_of::<Control>());
+    }
+}
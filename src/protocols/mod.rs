@@ -1,13 +1,36 @@
+#[cfg(target_os = "linux")]
+mod mmsg;
+mod options;
+#[cfg(target_os = "linux")]
+mod pacing;
+#[cfg(target_os = "linux")]
+mod sctp;
+#[cfg(target_os = "linux")]
+mod sockopt;
 mod tcp;
+mod tcp_info;
+#[cfg(target_os = "linux")]
+mod timestamps;
 mod udp;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
 
-use std::net::SocketAddr;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
+pub use options::{Dscp, PartialReliability, SctpOptions, SocketOptions, TcpOptions};
+#[cfg(target_os = "linux")]
+pub use sctp::{SctpReceiver, SctpSender};
 pub use tcp::{TcpReceiver, TcpSender};
+pub use tcp_info::{TcpInfo, TcpSettings};
 pub use udp::{UdpReceiver, UdpSender};
 
 use thiserror::Error;
 
+use crate::rate::Rate;
+
 /// Buffer size for receive operations
 ///
 /// Correlates to MAX_FRAME_SIZE in protocols
@@ -15,10 +38,121 @@ const RECV_BUF: usize = 65536;
 
 pub trait Sender {
     fn send(&mut self, data: &[u8]) -> Result<(), ProtoError>;
+
+    /// Sends `frames` in order, returns how many were sent before an error
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize, ProtoError> {
+        send_each(self, frames)
+    }
+
+    /// Frames worth collecting for a single `send_batch`
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Tells the receiver that the stream is over
+    ///
+    /// Can be called repeatedly, in case the previous notification was lost.
+    fn finish(&mut self) -> Result<(), ProtoError>;
+
+    /// Waits up to `timeout` for a reply from the receiver, zero doesn't block
+    fn recv_reply(&mut self, timeout: Duration) -> Result<Option<&[u8]>, ProtoError>;
+
+    /// Lets the kernel pace sending at `rate` with `SO_MAX_PACING_RATE`
+    ///
+    /// `rate` counts whole packets, as the kernel sees them.
+    fn set_pacing_rate(&mut self, _rate: &Rate) -> Result<(), ProtoError> {
+        Err(unsupported("SO_MAX_PACING_RATE"))
+    }
+
+    /// Sends the following frames with `SO_TXTIME` launch times spaced by `rate`
+    fn set_txtime(&mut self, _rate: &Rate) -> Result<(), ProtoError> {
+        Err(unsupported("SO_TXTIME"))
+    }
+
+    /// Has the kernel stamp sent datagrams as they enter the qdisc and reach the driver
+    fn set_tx_timestamps(&mut self) -> Result<(), ProtoError> {
+        Err(unsupported("SO_TIMESTAMPING"))
+    }
+
+    /// Passes the qdisc delay of every datagram stamped since the last call to `delay`
+    fn tx_delays(&mut self, _delay: &mut dyn FnMut(Duration)) {}
+
+    /// Transport state of the connection, for TCP on Linux
+    fn tcp_info(&self) -> Option<TcpInfo> {
+        None
+    }
 }
 
 pub trait Receiver {
-    fn recv<'a>(&mut self) -> Result<&[u8], ProtoError>;
+    fn recv(&mut self) -> Result<&[u8], ProtoError>;
+
+    /// Receives one or more frames, passing each to `handle` with its arrival time
+    ///
+    /// The time is the kernel's stamp where there is one, `None` otherwise.
+    /// Frames preceding an error are handled before it is returned.
+    fn recv_batch(
+        &mut self,
+        handle: &mut dyn FnMut(&[u8], Option<Instant>),
+    ) -> Result<usize, ProtoError> {
+        handle(self.recv()?, None);
+        Ok(1)
+    }
+
+    /// Sends `data` back to `peer`, even after it has disconnected
+    fn reply(&mut self, peer: SocketAddr, data: &[u8]) -> Result<(), ProtoError>;
+
+    /// Frames the local socket dropped so far for lack of buffer space, if the kernel tells
+    fn dropped(&self) -> Option<u64> {
+        None
+    }
+
+    /// Transport state of the latest connection, for TCP on Linux
+    fn tcp_info(&self) -> Option<TcpInfo> {
+        None
+    }
+
+    /// Whether frames arrive over a reliable byte stream, where the gaps
+    /// the handler counts as lost are records skipped by resyncs
+    fn is_stream(&self) -> bool {
+        false
+    }
+}
+
+/// Sends `frames` one by one, stopping at the first error
+fn send_each<S: Sender + ?Sized>(sender: &mut S, frames: &[&[u8]]) -> Result<usize, ProtoError> {
+    for (sent, frame) in frames.iter().enumerate() {
+        if let Err(e) = sender.send(frame) {
+            return if sent == 0 { Err(e) } else { Ok(sent) };
+        }
+    }
+    Ok(frames.len())
+}
+
+/// Whether the error means that no data is available yet
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Error for a socket option the platform or protocol lacks
+fn unsupported(option: &str) -> ProtoError {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{option} is not supported"),
+    )
+    .into()
+}
+
+/// Error for the io_uring backend missing from the build
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+fn uring_unsupported() -> ProtoError {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "io_uring needs a Linux build with the io-uring feature",
+    )
+    .into()
 }
 
 #[derive(Error, Debug)]
@@ -27,6 +161,11 @@ pub enum ProtoError {
     Connected(SocketAddr),
     #[error("Client {0} disconnected")]
     Disconnected(SocketAddr),
+    /// End of stream marker repeated after the session was closed, the results were lost
+    #[error("Client {0} repeated the end of its stream")]
+    Ended(SocketAddr),
+    #[error("Stream from {0} desynchronized: {1} bytes skipped")]
+    Desynchronized(SocketAddr, usize),
     #[error("Client {0} is not connected: datagram ignored")]
     ConflictingClient(SocketAddr),
     #[error("IO Error: {0}")]
@@ -0,0 +1,314 @@
+//! Options set on sockets of every transport before they bind or connect
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::{fmt, str::FromStr};
+
+#[cfg(target_os = "linux")]
+use super::sockopt::setsockopt;
+#[cfg(not(target_os = "linux"))]
+use super::unsupported;
+use super::ProtoError;
+
+/// Differentiated services codepoint, the upper 6 bits of the IP TOS byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dscp(pub u8);
+
+/// Class selectors, assured and expedited forwarding of RFC 2474, 2597, 3246 and 5865
+const NAMED: [(&str, u8); 24] = [
+    ("CS0", 0),
+    ("CS1", 8),
+    ("CS2", 16),
+    ("CS3", 24),
+    ("CS4", 32),
+    ("CS5", 40),
+    ("CS6", 48),
+    ("CS7", 56),
+    ("AF11", 10),
+    ("AF12", 12),
+    ("AF13", 14),
+    ("AF21", 18),
+    ("AF22", 20),
+    ("AF23", 22),
+    ("AF31", 26),
+    ("AF32", 28),
+    ("AF33", 30),
+    ("AF41", 34),
+    ("AF42", 36),
+    ("AF43", 38),
+    ("EF", 46),
+    ("VA", 44),
+    ("LE", 1),
+    ("BE", 0),
+];
+
+impl FromStr for Dscp {
+    type Err = String;
+
+    /// Takes a number below 64 or a name like `EF`, `AF41` or `CS1`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, dscp)) = NAMED.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+            return Ok(Self(*dscp));
+        }
+        match s.parse::<u8>() {
+            Ok(dscp) if dscp < 64 => Ok(Self(dscp)),
+            _ => Err(format!(
+                "invalid DSCP `{s}`: expected 0-63 or a name like EF, AF41, CS1"
+            )),
+        }
+    }
+}
+
+/// Options of a socket, unset ones keep the system defaults
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    /// `SO_SNDBUF`, the kernel doubles it for its bookkeeping
+    pub send_buffer: Option<u32>,
+    /// `SO_RCVBUF`, doubled as well
+    pub recv_buffer: Option<u32>,
+    /// DSCP in the upper 6 bits and ECN in the lower 2 of the TOS byte
+    pub tos: Option<u8>,
+    /// TTL of unicast packets
+    pub ttl: Option<u8>,
+    /// TTL of multicast packets, 1 unless set
+    pub multicast_ttl: Option<u8>,
+    /// Sets the DF bit with `IP_PMTUDISC_DO` or clears it with `IP_PMTUDISC_DONT`
+    pub dont_fragment: Option<bool>,
+    /// Interface for `SO_BINDTODEVICE`
+    pub device: Option<String>,
+}
+
+impl SocketOptions {
+    /// TOS byte of `dscp` and `ecn` codepoints, if either is set
+    pub fn tos(dscp: Option<Dscp>, ecn: Option<u8>) -> Option<u8> {
+        (dscp.is_some() || ecn.is_some())
+            .then(|| dscp.map_or(0, |dscp| dscp.0 << 2) | ecn.unwrap_or(0) & 0b11)
+    }
+
+    /// Sets the options on a socket which isn't bound or connected yet
+    #[cfg(target_os = "linux")]
+    pub(super) fn apply(&self, socket: &impl AsRawFd) -> Result<(), ProtoError> {
+        let fd = socket.as_raw_fd();
+        let int = |value: u32| value.min(i32::MAX as u32) as libc::c_int;
+        if let Some(size) = self.send_buffer {
+            setsockopt(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, &int(size))?;
+        }
+        if let Some(size) = self.recv_buffer {
+            setsockopt(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, &int(size))?;
+        }
+        if let Some(tos) = self.tos {
+            setsockopt(fd, libc::IPPROTO_IP, libc::IP_TOS, &int(tos.into()))?;
+        }
+        if let Some(ttl) = self.ttl {
+            setsockopt(fd, libc::IPPROTO_IP, libc::IP_TTL, &int(ttl.into()))?;
+        }
+        if let Some(df) = self.dont_fragment {
+            let discovery = match df {
+                true => libc::IP_PMTUDISC_DO,
+                false => libc::IP_PMTUDISC_DONT,
+            };
+            setsockopt(fd, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, &discovery)?;
+        }
+        if let Some(device) = &self.device {
+            setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                device.as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Options but the multicast TTL need Linux
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn apply<S>(&self, _socket: &S) -> Result<(), ProtoError> {
+        let options = [
+            (self.send_buffer.is_some(), "SO_SNDBUF"),
+            (self.recv_buffer.is_some(), "SO_RCVBUF"),
+            (self.tos.is_some(), "IP_TOS"),
+            (self.ttl.is_some(), "IP_TTL"),
+            (self.dont_fragment.is_some(), "IP_MTU_DISCOVER"),
+            (self.device.is_some(), "SO_BINDTODEVICE"),
+        ];
+        match options.iter().find(|(set, _)| *set) {
+            Some((_, option)) => Err(unsupported(option)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Options of TCP sockets, unset ones keep the system defaults
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// `TCP_CONGESTION` algorithm, like cubic, reno or bbr
+    pub congestion: Option<String>,
+    /// `TCP_NODELAY`, segments go out without waiting for outstanding ACKs
+    pub nodelay: bool,
+    /// `TCP_NOTSENT_LOWAT`, unsent bytes below which the socket takes more writes
+    pub notsent_lowat: Option<u32>,
+    /// `TCP_MAXSEG`, clamps the MSS of the connection
+    pub mss: Option<u16>,
+    /// Size of the buffer coalescing the sender's writes, 0 writes every frame at once
+    pub write_buffer: Option<usize>,
+    /// Opens `IPPROTO_MPTCP` sockets, plain TCP ones where the kernel lacks them
+    pub multipath: bool,
+}
+
+impl TcpOptions {
+    /// Sets the options on a socket before it connects or listens, accepted ones inherit them
+    #[cfg(target_os = "linux")]
+    pub(super) fn apply(&self, socket: &impl AsRawFd) -> Result<(), ProtoError> {
+        let fd = socket.as_raw_fd();
+        if let Some(congestion) = &self.congestion {
+            setsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_CONGESTION,
+                congestion.as_bytes(),
+            )
+            .map_err(|e| {
+                // ENOENT for algorithms the kernel doesn't have
+                std::io::Error::new(
+                    e.kind(),
+                    format!("congestion control {congestion} is unavailable: {e}"),
+                )
+            })?;
+        }
+        if self.nodelay {
+            setsockopt(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY, &1_i32)?;
+        }
+        if let Some(lowat) = self.notsent_lowat {
+            setsockopt(fd, libc::IPPROTO_TCP, libc::TCP_NOTSENT_LOWAT, &lowat)?;
+        }
+        if let Some(mss) = self.mss {
+            setsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_MAXSEG,
+                &libc::c_int::from(mss),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Options but the write buffer need Linux
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn apply<S>(&self, _socket: &S) -> Result<(), ProtoError> {
+        let options = [
+            (self.congestion.is_some(), "TCP_CONGESTION"),
+            (self.nodelay, "TCP_NODELAY"),
+            (self.notsent_lowat.is_some(), "TCP_NOTSENT_LOWAT"),
+            (self.mss.is_some(), "TCP_MAXSEG"),
+        ];
+        match options.iter().find(|(set, _)| *set) {
+            Some((_, option)) => Err(unsupported(option)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Partial reliability of PR-SCTP, RFC 3758, messages are abandoned instead of delivered late
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialReliability {
+    /// Abandoned unless sent within this many milliseconds, `SCTP_PR_SCTP_TTL`
+    Timed(u32),
+    /// Abandoned after this many retransmissions, `SCTP_PR_SCTP_RTX`
+    Retransmissions(u32),
+}
+
+impl FromStr for PartialReliability {
+    type Err = String;
+
+    /// Takes `ttl:MS` or `rtx:COUNT`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (policy, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid PR-SCTP policy `{s}`: expected ttl:MS or rtx:COUNT"))?;
+        let value = value
+            .parse()
+            .map_err(|_| format!("invalid PR-SCTP value `{value}` in `{s}`"))?;
+        match policy {
+            "ttl" if value > 0 => Ok(Self::Timed(value)),
+            "ttl" => Err(format!("PR-SCTP lifetime has to be positive in `{s}`")),
+            "rtx" => Ok(Self::Retransmissions(value)),
+            _ => Err(format!(
+                "invalid PR-SCTP policy `{policy}`: expected ttl or rtx"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PartialReliability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timed(ms) => write!(f, "lifetime {ms}ms"),
+            Self::Retransmissions(count) => write!(f, "up to {count} retransmissions"),
+        }
+    }
+}
+
+/// Options of SCTP sockets, unset ones keep the system defaults
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SctpOptions {
+    /// Streams to ask for in both directions with `SCTP_INITMSG`, frames take them round robin
+    ///
+    /// All frames go on stream 0 unless set.
+    pub streams: Option<u16>,
+    /// Frames are delivered as they arrive, not in the order of their stream
+    pub unordered: bool,
+    /// PR-SCTP policy of the frames, fully reliable unless set
+    pub reliability: Option<PartialReliability>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dscp() {
+        assert_eq!("ef".parse(), Ok(Dscp(46)));
+        assert_eq!("AF41".parse(), Ok(Dscp(34)));
+        assert_eq!("63".parse(), Ok(Dscp(63)));
+        assert!("64".parse::<Dscp>().is_err());
+        // EF with ECT(0)
+        assert_eq!(SocketOptions::tos(Some(Dscp(46)), Some(0b10)), Some(0xba));
+        assert_eq!(SocketOptions::tos(None, None), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_apply() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let options = SocketOptions {
+            send_buffer: Some(65536),
+            tos: SocketOptions::tos(Some(Dscp(46)), None),
+            ttl: Some(7),
+            dont_fragment: Some(true),
+            ..Default::default()
+        };
+        options.apply(&socket).unwrap();
+        let get = |level, name| {
+            let mut value: libc::c_int = 0;
+            let mut len = std::mem::size_of_val(&value) as libc::socklen_t;
+            let value_ptr = &mut value as *mut libc::c_int as *mut libc::c_void;
+            unsafe { libc::getsockopt(socket.as_raw_fd(), level, name, value_ptr, &mut len) };
+            value
+        };
+        assert_eq!(get(libc::IPPROTO_IP, libc::IP_TOS), 46 << 2);
+        assert_eq!(get(libc::IPPROTO_IP, libc::IP_TTL), 7);
+        assert_eq!(
+            get(libc::IPPROTO_IP, libc::IP_MTU_DISCOVER),
+            libc::IP_PMTUDISC_DO
+        );
+        assert_eq!(get(libc::SOL_SOCKET, libc::SO_SNDBUF), 2 * 65536);
+    }
+
+    #[test]
+    fn test_partial_reliability() {
+        assert_eq!("ttl:100".parse(), Ok(PartialReliability::Timed(100)));
+        assert_eq!("rtx:0".parse(), Ok(PartialReliability::Retransmissions(0)));
+        assert!("ttl:0".parse::<PartialReliability>().is_err());
+        assert!("prio:1".parse::<PartialReliability>().is_err());
+        assert!("rtx".parse::<PartialReliability>().is_err());
+    }
+}
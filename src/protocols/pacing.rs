@@ -0,0 +1,133 @@
+//! Pacing offloaded to the kernel, Linux only
+//!
+//! `SO_MAX_PACING_RATE` caps the socket rate, enforced by TCP itself or by
+//! the fq qdisc for UDP. `SO_TXTIME` attaches a launch time to every datagram,
+//! honoured by the fq and etf qdiscs.
+use std::{
+    io,
+    mem::size_of,
+    os::fd::{AsRawFd, RawFd},
+    time::Duration,
+};
+
+use super::sockopt::setsockopt;
+use crate::rate::Rate;
+
+/// How far ahead of time datagrams are handed to the kernel
+const HORIZON: u64 = 1_000_000;
+
+/// Frames the socket may keep queued below it
+///
+/// Writes have to block soon to follow the pacing. fq also drops instead of
+/// blocking the socket past its flow limit of 100 packets.
+const QUEUED_FRAMES: usize = 32;
+
+/// Keeps at most a few frames of `rate` queued below the socket,
+/// unless the user has set `send_buffer`
+fn limit_queue(fd: RawFd, rate: &Rate, send_buffer: Option<u32>) -> io::Result<()> {
+    let size = QUEUED_FRAMES * usize::from(rate.frame_size());
+    match send_buffer {
+        Some(set) if set as usize > size => {
+            eprintln!(
+                "Send buffer of {set} bytes queues over {QUEUED_FRAMES} frames, \
+                 writes may run ahead of the pacing"
+            );
+            Ok(())
+        }
+        Some(_) => Ok(()),
+        // Kernel doubles the value to account for its bookkeeping
+        None => setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            &(size as libc::c_int),
+        ),
+    }
+}
+
+/// Caps the rate of `socket` with `SO_MAX_PACING_RATE`
+pub fn set_max_pacing_rate(
+    socket: &impl AsRawFd,
+    rate: &Rate,
+    send_buffer: Option<u32>,
+) -> io::Result<()> {
+    let fd = socket.as_raw_fd();
+    // u32::MAX means no limit
+    let bytes = rate.bytes_per_second().min(f64::from(u32::MAX - 1)) as u32;
+    setsockopt(fd, libc::SOL_SOCKET, libc::SO_MAX_PACING_RATE, &bytes)?;
+    limit_queue(fd, rate, send_buffer)
+}
+
+fn monotonic_now() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+/// Schedule of datagram launch times for `SO_TXTIME`
+pub struct TxTime {
+    /// Time between launches, ns
+    gap: u64,
+    /// Launch time of the next datagram, ns of `CLOCK_MONOTONIC`
+    next: u64,
+}
+
+impl TxTime {
+    /// Enables launch times on `socket`, spaced to match `rate`
+    pub fn enable(
+        socket: &impl AsRawFd,
+        rate: &Rate,
+        send_buffer: Option<u32>,
+    ) -> io::Result<Self> {
+        let fd = socket.as_raw_fd();
+        let config = libc::sock_txtime {
+            clockid: libc::CLOCK_MONOTONIC,
+            flags: 0,
+        };
+        setsockopt(fd, libc::SOL_SOCKET, libc::SO_TXTIME, &config)?;
+        limit_queue(fd, rate, send_buffer)?;
+        Ok(Self {
+            gap: (1e9 / rate.frames_per_second()) as u64,
+            next: 0,
+        })
+    }
+
+    /// Sends `data` at its launch time
+    ///
+    /// Sleeps when the schedule gets more than twice the horizon ahead.
+    pub fn send(&mut self, socket: &impl AsRawFd, data: &[u8]) -> io::Result<usize> {
+        let now = monotonic_now();
+        let launch = self.next.max(now);
+        self.next = launch + self.gap;
+        if launch > now + 2 * HORIZON {
+            std::thread::sleep(Duration::from_nanos(launch - now - HORIZON));
+        }
+
+        let mut iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        // Room for a single u64, aligned for cmsghdr
+        let mut control = [0_u64; 4];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(size_of::<u64>() as u32) } as _;
+        let res = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_TXTIME;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u64>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u64, launch);
+            libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+        };
+        match res {
+            -1 => Err(io::Error::last_os_error()),
+            sent => Ok(sent as usize),
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! SCTP transport over one-to-one sockets, Linux only
+//!
+//! Frames are SCTP messages spread round robin over the streams of the
+//! association, unordered and partially reliable if asked to, so loss shows up
+//! the way signalling stacks over SCTP see it. One-to-one sockets take the calls
+//! of std's TCP types, every read and write being a whole message.
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::time::{Duration, Instant};
+
+use net2::TcpBuilder;
+
+use super::sockopt::{getsockopt, setsockopt};
+use super::{
+    is_timeout, PartialReliability, ProtoError, Receiver, SctpOptions, Sender, SocketOptions,
+    RECV_BUF,
+};
+
+/// Level of SCTP socket options and control messages, `SOL_SCTP`
+const SOL_SCTP: libc::c_int = libc::IPPROTO_SCTP;
+
+/// `SCTP_INITMSG`
+const SCTP_INITMSG: libc::c_int = 2;
+
+/// `SCTP_STATUS`
+const SCTP_STATUS: libc::c_int = 14;
+
+/// Control message with the stream and flags of a sent message, `SCTP_SNDINFO`
+const SCTP_SNDINFO: libc::c_int = 2;
+
+/// Control message with the PR-SCTP policy of a sent message, `SCTP_PRINFO`
+const SCTP_PRINFO: libc::c_int = 5;
+
+/// `SCTP_UNORDERED`
+const SCTP_UNORDERED: u16 = 1;
+
+/// `SCTP_PR_SCTP_TTL`
+const SCTP_PR_SCTP_TTL: u16 = 0x10;
+
+/// `SCTP_PR_SCTP_RTX`
+const SCTP_PR_SCTP_RTX: u16 = 0x20;
+
+/// Message ending the stream, as the datagram of UDP
+const END: [u8; 1] = [0];
+
+/// Longest wait for the frames in flight to be acknowledged before the end is sent
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads of an association give up after this, so an idle peer doesn't block the receiver
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Room for the send info and PR-SCTP policy of a message, aligned for cmsghdr
+type Control = [u64; 8];
+
+/// `struct sctp_initmsg`
+#[repr(C)]
+struct InitMsg {
+    num_ostreams: u16,
+    max_instreams: u16,
+    max_attempts: u16,
+    max_init_timeo: u16,
+}
+
+/// `struct sctp_sndinfo`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SndInfo {
+    sid: u16,
+    flags: u16,
+    ppid: u32,
+    context: u32,
+    assoc_id: i32,
+}
+
+/// `struct sctp_prinfo`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PrInfo {
+    policy: u16,
+    value: u32,
+}
+
+impl From<PartialReliability> for PrInfo {
+    fn from(reliability: PartialReliability) -> Self {
+        match reliability {
+            PartialReliability::Timed(ms) => Self {
+                policy: SCTP_PR_SCTP_TTL,
+                value: ms,
+            },
+            PartialReliability::Retransmissions(count) => Self {
+                policy: SCTP_PR_SCTP_RTX,
+                value: count,
+            },
+        }
+    }
+}
+
+/// `struct sctp_status` with the packed `struct sctp_paddrinfo` of the primary path
+#[repr(C)]
+#[derive(Default)]
+struct Status {
+    assoc_id: i32,
+    state: i32,
+    rwnd: u32,
+    unackdata: u16,
+    penddata: u16,
+    instrms: u16,
+    outstrms: u16,
+    fragmentation_point: u32,
+    primary_assoc_id: i32,
+    /// `struct sockaddr_storage`
+    primary_address: [u32; 32],
+    primary_state: i32,
+    primary_cwnd: u32,
+    primary_srtt: u32,
+    primary_rto: u32,
+    primary_mtu: u32,
+}
+
+impl Status {
+    /// Status of the association of a connected socket
+    fn of(socket: &impl AsRawFd) -> io::Result<Self> {
+        let mut status = Self::default();
+        getsockopt(socket.as_raw_fd(), SOL_SCTP, SCTP_STATUS, &mut status)?;
+        Ok(status)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} outbound and {} inbound streams, path MTU {}, RTO {}ms",
+            self.outstrms, self.instrms, self.primary_mtu, self.primary_rto
+        )
+    }
+}
+
+/// One-to-one SCTP socket asking for the streams of `sctp`
+fn socket(options: &SocketOptions, sctp: &SctpOptions) -> anyhow::Result<TcpBuilder> {
+    let kind = libc::SOCK_STREAM | libc::SOCK_CLOEXEC;
+    let fd = unsafe { libc::socket(libc::AF_INET, kind, libc::IPPROTO_SCTP) };
+    if fd < 0 {
+        // EPROTONOSUPPORT without the kernel's sctp module
+        anyhow::bail!("SCTP is unavailable: {}", io::Error::last_os_error());
+    }
+    let socket = unsafe { TcpBuilder::from_raw_fd(fd) };
+    options.apply(&socket)?;
+    if let Some(streams) = sctp.streams {
+        let init = InitMsg {
+            num_ostreams: streams,
+            max_instreams: streams,
+            max_attempts: 0,
+            max_init_timeo: 0,
+        };
+        setsockopt(fd, SOL_SCTP, SCTP_INITMSG, &init)?;
+    }
+    Ok(socket)
+}
+
+/// Fills `cmsg` with an SCTP control message carrying `data`
+unsafe fn write_cmsg<T>(cmsg: *mut libc::cmsghdr, kind: libc::c_int, data: T) {
+    (*cmsg).cmsg_level = SOL_SCTP;
+    (*cmsg).cmsg_type = kind;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<T>() as u32) as _;
+    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut T, data);
+}
+
+pub struct SctpSender {
+    socket: TcpStream,
+    /// Outbound streams of the association, frames take them round robin
+    streams: u16,
+    /// Stream of the next frame
+    next: u16,
+    /// `SCTP_UNORDERED` or none
+    flags: u16,
+    /// PR-SCTP policy of the frames, the end of the stream is always reliable
+    reliability: Option<PrInfo>,
+    buf: Box<[u8]>,
+    finished: bool,
+}
+
+impl SctpSender {
+    pub fn new(
+        peer: Ipv4Addr,
+        port: u16,
+        _bind: Ipv4Addr,
+        options: &SocketOptions,
+        sctp: &SctpOptions,
+    ) -> anyhow::Result<Self> {
+        let socket = socket(options, sctp)?.connect((peer, port))?;
+        println!("Connected to server {peer}:{port}");
+        let status = Status::of(&socket)?;
+        print!("SCTP settings: {status}");
+        if sctp.unordered {
+            print!(", unordered");
+        }
+        match sctp.reliability {
+            Some(reliability) => println!(", PR-SCTP {reliability}"),
+            None => println!(", fully reliable"),
+        }
+        Ok(Self {
+            socket,
+            // the peer may take fewer streams than asked for
+            streams: sctp.streams.map_or(1, |_| status.outstrms.max(1)),
+            next: 0,
+            flags: if sctp.unordered { SCTP_UNORDERED } else { 0 },
+            reliability: sctp.reliability.map(PrInfo::from),
+            buf: vec![0; RECV_BUF].into_boxed_slice(),
+            finished: false,
+        })
+    }
+
+    /// Sends `data` as a single message with `info` and the PR-SCTP policy if any
+    fn send_message(
+        &self,
+        data: &[u8],
+        info: SndInfo,
+        reliability: Option<PrInfo>,
+    ) -> io::Result<()> {
+        let mut iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut control = Control::default();
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(size_of::<SndInfo>() as u32) } as _;
+        if reliability.is_some() {
+            msg.msg_controllen += unsafe { libc::CMSG_SPACE(size_of::<PrInfo>() as u32) } as usize;
+        }
+        let res = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            write_cmsg(cmsg, SCTP_SNDINFO, info);
+            if let Some(reliability) = reliability {
+                write_cmsg(libc::CMSG_NXTHDR(&msg, cmsg), SCTP_PRINFO, reliability);
+            }
+            libc::sendmsg(self.socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL)
+        };
+        match res {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits up to `DRAIN_TIMEOUT` until no frame is queued or unacknowledged
+    fn drain(&self) -> io::Result<()> {
+        let start = Instant::now();
+        loop {
+            let status = Status::of(&self.socket)?;
+            if status.unackdata == 0 && status.penddata == 0 {
+                return Ok(());
+            }
+            if start.elapsed() >= DRAIN_TIMEOUT {
+                eprintln!(
+                    "Frames still in flight after {DRAIN_TIMEOUT:?}: {} unacknowledged, {} queued",
+                    status.unackdata, status.penddata
+                );
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Sender for SctpSender {
+    fn send(&mut self, data: &[u8]) -> Result<(), ProtoError> {
+        let info = SndInfo {
+            sid: self.next,
+            flags: self.flags,
+            ..Default::default()
+        };
+        self.next = (self.next + 1) % self.streams;
+        if let Err(e) = self.send_message(data, info, self.reliability) {
+            eprintln!("Disconnected from server. Reason: {e}");
+            return Err(ProtoError::Disconnected(self.socket.peer_addr()?));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ProtoError> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        // frames of the other streams or unordered ones could overtake the end otherwise
+        self.drain()?;
+        self.send_message(&END, SndInfo::default(), None)?;
+        Ok(())
+    }
+
+    fn recv_reply(&mut self, timeout: Duration) -> Result<Option<&[u8]>, ProtoError> {
+        if timeout.is_zero() {
+            self.socket.set_nonblocking(true)?;
+        } else {
+            self.socket.set_read_timeout(Some(timeout))?;
+        }
+        let res = self.socket.read(&mut self.buf);
+        self.socket.set_nonblocking(false)?;
+        match res {
+            Ok(0) => Ok(None),
+            Ok(size) => Ok(Some(&self.buf[..size])),
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for SctpSender {
+    fn drop(&mut self) {
+        let _x = self.finish();
+    }
+}
+
+pub struct SctpReceiver {
+    socket: TcpListener,
+    /// Association of the last peer, kept after the end of its stream for replies
+    peer: Option<(TcpStream, SocketAddr)>,
+    /// Frames of the peer are still coming
+    receiving: bool,
+    buf: Box<[u8]>,
+}
+
+impl Receiver for SctpReceiver {
+    fn recv(&mut self) -> Result<&[u8], ProtoError> {
+        match &self.peer {
+            Some((stream, addr)) if self.receiving => {
+                let addr = *addr;
+                match (&*stream).read(&mut self.buf) {
+                    Ok(size) if size > 0 && self.buf[..size] != END => Ok(&self.buf[..size]),
+                    // the association is still up, the caller gets to tick
+                    Err(e) if is_timeout(&e) || e.kind() == io::ErrorKind::Interrupted => {
+                        Err(e.into())
+                    }
+                    Ok(_) | Err(_) => {
+                        self.receiving = false;
+                        Err(ProtoError::Disconnected(addr))
+                    }
+                }
+            }
+            _ => {
+                let (stream, addr) = self.socket.accept()?;
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                if let Ok(status) = Status::of(&stream) {
+                    println!("SCTP settings: {status}");
+                }
+                self.peer = Some((stream, addr));
+                self.receiving = true;
+                Err(ProtoError::Connected(addr))
+            }
+        }
+    }
+
+    fn reply(&mut self, peer: SocketAddr, data: &[u8]) -> Result<(), ProtoError> {
+        if let Some((stream, _)) = self.peer.as_ref().filter(|(_, addr)| *addr == peer) {
+            (&*stream).write_all(data)?;
+        }
+        Ok(())
+    }
+}
+
+impl SctpReceiver {
+    /// Accepted associations inherit `options` and the streams of `sctp` from the listener
+    pub fn new(
+        bind: Ipv4Addr,
+        port: u16,
+        options: &SocketOptions,
+        sctp: &SctpOptions,
+    ) -> anyhow::Result<Self> {
+        let listener = socket(options, sctp)?;
+        listener.reuse_address(true)?;
+        let listener = listener.bind((bind, port))?.listen(128)?;
+        Ok(Self {
+            socket: listener,
+            peer: None,
+            receiving: false,
+            buf: vec![0; RECV_BUF].into_boxed_slice(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        // sizes of the kernel's structures, sctp_paddrinfo being packed
+        assert_eq!(size_of::<InitMsg>(), 8);
+        assert_eq!(size_of::<SndInfo>(), 16);
+        assert_eq!(size_of::<PrInfo>(), 8);
+        assert_eq!(size_of::<Status>(), 176);
+        let control = unsafe { libc::CMSG_SPACE(16) + libc::CMSG_SPACE(8) } as usize;
+        assert!(control <= size_of::<Control>());
+    }
+
+    #[test]
+    fn test_loopback() {
+        let sctp = SctpOptions {
+            streams: Some(4),
+            unordered: true,
+            reliability: Some(PartialReliability::Timed(1000)),
+        };
+        let options = SocketOptions::default();
+        // the kernel's sctp module may be missing or blacklisted
+        if let Err(e) = socket(&options, &sctp) {
+            eprintln!("{e}, skipping");
+            return;
+        }
+        let mut receiver = SctpReceiver::new(Ipv4Addr::LOCALHOST, 0, &options, &sctp).unwrap();
+        let port = receiver.socket.local_addr().unwrap().port();
+        let mut sender = SctpSender::new(
+            Ipv4Addr::LOCALHOST,
+            port,
+            Ipv4Addr::UNSPECIFIED,
+            &options,
+            &sctp,
+        )
+        .unwrap();
+        assert_eq!(sender.streams, 4);
+        let Err(ProtoError::Connected(peer)) = receiver.recv() else {
+            panic!("no association");
+        };
+        for frame in 1..=8 {
+            sender.send(&[frame; 100]).unwrap();
+        }
+        let mut frames: Vec<u8> = (0..8).map(|_| receiver.recv().unwrap()[0]).collect();
+        frames.sort();
+        assert_eq!(frames, (1..=8).collect::<Vec<_>>());
+        sender.finish().unwrap();
+        assert!(matches!(receiver.recv(), Err(ProtoError::Disconnected(_))));
+        receiver.reply(peer, b"results").unwrap();
+        let reply = sender.recv_reply(Duration::from_secs(1)).unwrap();
+        assert_eq!(reply, Some(&b"results"[..]));
+    }
+}
@@ -0,0 +1,46 @@
+//! Raw socket options which std and net2 don't cover, Linux only
+use std::{io, mem::size_of_val, os::fd::RawFd};
+
+pub fn setsockopt<T: ?Sized>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value as *const T as *const libc::c_void,
+            size_of_val(value) as libc::socklen_t,
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Reads an option into `value`, returns how many bytes the kernel filled in
+pub fn getsockopt<T>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &mut T,
+) -> io::Result<usize> {
+    let mut len = size_of_val(value) as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            name,
+            value as *mut T as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    match res {
+        0 => Ok(len as usize),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
@@ -1,24 +1,144 @@
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::{Ipv4Addr, TcpListener, TcpStream, SocketAddr};
+use net2::TcpBuilder;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
 
-use super::{ProtoError, Receiver, Sender, RECV_BUF};
+use crate::framing::{Framing, Record};
+#[cfg(target_os = "linux")]
+use crate::rate::Rate;
+
+#[cfg(target_os = "linux")]
+use super::pacing;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use super::uring::{self, StreamReader, Writer};
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+use super::uring_unsupported;
+use super::{is_timeout, ProtoError, Receiver, Sender, SocketOptions, TcpOptions, RECV_BUF};
+#[cfg(target_os = "linux")]
+use super::{TcpInfo, TcpSettings};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use std::os::fd::AsRawFd;
+
+/// Size of the length prefix of replies
+const REPLY_HEADER: usize = 4;
+
+/// Size of the sender's write buffer unless set, as `BufWriter`'s default
+const WRITE_BUFFER: usize = 8192;
+
+/// Replies waiting for a client that doesn't read them are dropped past that size
+const REPLY_BACKLOG: usize = 64 * 1024;
+
+/// Longest a reply may hold up the receive path
+const REPLY_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Longest the final results may wait for the client before the next accept
+const FINAL_REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Stream socket builder, of MPTCP if `tcp` asks for it and the kernel has it
+fn stream_builder(tcp: &TcpOptions) -> std::io::Result<TcpBuilder> {
+    if tcp.multipath {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::FromRawFd;
+            let kind = libc::SOCK_STREAM | libc::SOCK_CLOEXEC;
+            let fd = unsafe { libc::socket(libc::AF_INET, kind, libc::IPPROTO_MPTCP) };
+            if fd >= 0 {
+                return Ok(unsafe { TcpBuilder::from_raw_fd(fd) });
+            }
+            let e = std::io::Error::last_os_error();
+            eprintln!("MPTCP is unavailable, falling back to TCP: {e}");
+        }
+        #[cfg(not(target_os = "linux"))]
+        eprintln!("MPTCP is Linux only, falling back to TCP");
+    }
+    TcpBuilder::new_v4()
+}
+
+/// Prints the settings the kernel applied to a new connection
+fn print_settings(stream: &TcpStream, write_buffer: Option<usize>) {
+    #[cfg(target_os = "linux")]
+    if let Ok(settings) = TcpSettings::of(stream) {
+        print!("TCP settings: {settings}");
+    }
+    #[cfg(not(target_os = "linux"))]
+    print!(
+        "TCP settings: no delay {}",
+        stream.nodelay().unwrap_or_default()
+    );
+    match write_buffer {
+        Some(0) => println!(", unbuffered writes"),
+        Some(size) => println!(", write buffer {size} bytes"),
+        None => println!(),
+    }
+}
 
 pub struct TcpSender {
     socket: BufWriter<TcpStream>,
+    framing: Box<dyn Framing>,
+    finished: bool,
+    /// Length-prefixed replies read from the stream so far
+    replies: Vec<u8>,
+    /// Length of the reply returned by the previous call
+    consumed: usize,
+    /// `SO_SNDBUF` set by the user, kept by kernel pacing
+    #[cfg(target_os = "linux")]
+    send_buffer: Option<u32>,
+    /// Frames go through io_uring instead, along with the most of them per batch
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: Option<(Writer, usize)>,
 }
 
 impl TcpSender {
-    pub fn new(peer: Ipv4Addr, port: u16, _bind: Ipv4Addr) -> anyhow::Result<Self> {
-        let socket = TcpStream::connect((peer, port))?;
+    pub fn new(
+        peer: Ipv4Addr,
+        port: u16,
+        _bind: Ipv4Addr,
+        framing: Box<dyn Framing>,
+        options: &SocketOptions,
+        tcp: &TcpOptions,
+    ) -> anyhow::Result<Self> {
+        let socket = stream_builder(tcp)?;
+        options.apply(&socket)?;
+        tcp.apply(&socket)?;
+        let socket = socket.connect((peer, port))?;
         println!("Connected to server {peer}:{port}");
+        let write_buffer = tcp.write_buffer.unwrap_or(WRITE_BUFFER);
+        print_settings(&socket, Some(write_buffer));
         Ok(Self {
-            socket: BufWriter::new(socket),
+            // frames at least as large as the buffer are written right away
+            socket: BufWriter::with_capacity(write_buffer, socket),
+            framing,
+            finished: false,
+            replies: vec![],
+            consumed: 0,
+            #[cfg(target_os = "linux")]
+            send_buffer: options.send_buffer,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring: None,
         })
     }
+
+    /// Sends through io_uring from a registered buffer, up to `batch` frames at once
+    pub fn set_uring(&mut self, batch: usize) -> Result<(), ProtoError> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            self.uring = Some((Writer::new()?, batch));
+            Ok(())
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        {
+            let _ = batch;
+            Err(uring_unsupported())
+        }
+    }
 }
 
 impl Sender for TcpSender {
     fn send(&mut self, data: &[u8]) -> Result<(), ProtoError> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if self.uring.is_some() {
+            return self.send_batch(&[data]).map(drop);
+        }
         if let Err(e) = self.socket.write_all(data) {
             eprintln!("Disconnected from server. Reason: {e}");
             Err(ProtoError::Disconnected(self.socket.get_ref().peer_addr()?))
@@ -26,46 +146,226 @@ impl Sender for TcpSender {
             Ok(())
         }
     }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize, ProtoError> {
+        let Some((writer, _)) = &mut self.uring else {
+            return super::send_each(self, frames);
+        };
+        match writer.send_batch(self.socket.get_ref().as_raw_fd(), frames) {
+            Ok(sent) => Ok(sent),
+            Err(e) => {
+                eprintln!("Disconnected from server. Reason: {e}");
+                Err(ProtoError::Disconnected(self.socket.get_ref().peer_addr()?))
+            }
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn batch_size(&self) -> usize {
+        match &self.uring {
+            Some((writer, batch)) => (*batch).clamp(1, writer.batch_size()),
+            None => 1,
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), ProtoError> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.socket.write_all(self.framing.terminator())?;
+        self.socket.flush()?;
+        self.socket.get_ref().shutdown(Shutdown::Write)?;
+        Ok(())
+    }
+
+    fn recv_reply(&mut self, timeout: Duration) -> Result<Option<&[u8]>, ProtoError> {
+        self.replies.drain(..self.consumed);
+        self.consumed = 0;
+        loop {
+            if let Some(len) = self.replies.get(..REPLY_HEADER) {
+                let len = u32::from_be_bytes(len.try_into().expect("4 bytes")) as usize;
+                if self.replies.len() >= REPLY_HEADER + len {
+                    self.consumed = REPLY_HEADER + len;
+                    return Ok(Some(&self.replies[REPLY_HEADER..self.consumed]));
+                }
+            }
+            let mut stream = self.socket.get_ref();
+            if timeout.is_zero() {
+                stream.set_nonblocking(true)?;
+            } else {
+                stream.set_read_timeout(Some(timeout))?;
+            }
+            let mut chunk = [0; 1024];
+            let res = stream.read(&mut chunk);
+            stream.set_nonblocking(false)?;
+            match res {
+                Ok(0) => return Ok(None),
+                Ok(size) => self.replies.extend_from_slice(&chunk[..size]),
+                Err(e) if is_timeout(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_pacing_rate(&mut self, rate: &Rate) -> Result<(), ProtoError> {
+        pacing::set_max_pacing_rate(self.socket.get_ref(), rate, self.send_buffer)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tcp_info(&self) -> Option<TcpInfo> {
+        TcpInfo::of(self.socket.get_ref()).ok()
+    }
 }
 
 impl Drop for TcpSender {
     fn drop(&mut self) {
-        let _x = self.socket.write_all(&[0]);
+        let _x = self.finish();
     }
 }
 
+/// Writes queued replies until they are gone or the socket has been full for `timeout`,
+/// whole replies only enter `outbox` so partial writes keep the stream framed
+fn flush_replies(
+    mut stream: &TcpStream,
+    outbox: &mut Vec<u8>,
+    timeout: Duration,
+) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(timeout))?;
+    while !outbox.is_empty() {
+        match stream.write(outbox) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(size) => {
+                outbox.drain(..size);
+            }
+            Err(e) if is_timeout(&e) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
 pub struct TcpReceiver {
     socket: TcpListener,
-    connection: Option<(BufReader<TcpStream>, SocketAddr)>,
+    connection: Option<(Box<dyn BufRead>, SocketAddr)>,
+    /// Write half of the last connection, kept open for replies
+    replier: Option<(TcpStream, SocketAddr)>,
+    /// Length-prefixed replies the write half hasn't taken yet
+    outbox: Vec<u8>,
     buf: Vec<u8>,
+    framing: Box<dyn Framing>,
+    /// Record read after a resync, returned on the next call
+    pending: bool,
+    /// Connections are read with a multishot io_uring receive
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: bool,
 }
 
 impl Receiver for TcpReceiver {
-    fn recv<'a>(&mut self) -> Result<&[u8], ProtoError> {
-        self.buf.clear();
+    fn recv(&mut self) -> Result<&[u8], ProtoError> {
+        if self.pending {
+            self.pending = false;
+            return Ok(&self.buf[..]);
+        }
         if let Some((mut conn, addr)) = self.connection.take() {
-            if conn.read_until(0, &mut self.buf).is_err() || (self.buf.len() == 1 && self.buf[0] == 0) {
-                return Err(ProtoError::Disconnected(addr))
-            } else {
-                self.connection = Some((conn, addr));
-                return Ok(&self.buf[..]);
-            };
+            match self.framing.read_record(&mut conn, &mut self.buf) {
+                Ok(Record::Data { skipped: 0 }) => {
+                    self.connection = Some((conn, addr));
+                    Ok(&self.buf[..])
+                }
+                Ok(Record::Data { skipped }) => {
+                    self.connection = Some((conn, addr));
+                    self.pending = true;
+                    Err(ProtoError::Desynchronized(addr, skipped))
+                }
+                Ok(Record::End) | Err(_) => Err(ProtoError::Disconnected(addr)),
+            }
         } else {
+            if let Some((stream, addr)) = &self.replier {
+                if let Err(e) = flush_replies(stream, &mut self.outbox, FINAL_REPLY_TIMEOUT) {
+                    eprintln!("Failed to send replies to {addr}: {e}");
+                }
+            }
+            self.outbox.clear();
             let (conn, addr) = self.socket.accept()?;
-            self.connection = Some((BufReader::new(conn), addr));
-            return Err(ProtoError::Connected(addr));
+            print_settings(&conn, None);
+            self.replier = Some((conn.try_clone()?, addr));
+            let reader: Box<dyn BufRead> = match () {
+                #[cfg(all(target_os = "linux", feature = "io-uring"))]
+                _ if self.uring => Box::new(StreamReader::new(conn)?),
+                _ => Box::new(BufReader::new(conn)),
+            };
+            self.connection = Some((reader, addr));
+            Err(ProtoError::Connected(addr))
         }
     }
+
+    /// Queues the reply and writes what the socket takes within `REPLY_TIMEOUT`
+    fn reply(&mut self, peer: SocketAddr, data: &[u8]) -> Result<(), ProtoError> {
+        if let Some((stream, _)) = self.replier.as_ref().filter(|(_, addr)| *addr == peer) {
+            if self.outbox.len() < REPLY_BACKLOG {
+                self.outbox
+                    .extend_from_slice(&(data.len() as u32).to_be_bytes());
+                self.outbox.extend_from_slice(data);
+            }
+            flush_replies(stream, &mut self.outbox, REPLY_TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tcp_info(&self) -> Option<TcpInfo> {
+        let (stream, _) = self.replier.as_ref()?;
+        TcpInfo::of(stream).ok()
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
 }
 
 impl TcpReceiver {
-    pub fn new(bind: Ipv4Addr, port: u16) -> anyhow::Result<Self> {
-        let listener = TcpListener::bind((bind, port))?;
+    /// Accepted connections inherit `options` and `tcp` from the listener
+    pub fn new(
+        bind: Ipv4Addr,
+        port: u16,
+        framing: Box<dyn Framing>,
+        options: &SocketOptions,
+        tcp: &TcpOptions,
+    ) -> anyhow::Result<Self> {
+        let listener = stream_builder(tcp)?;
+        // as `TcpListener::bind` does
+        #[cfg(unix)]
+        listener.reuse_address(true)?;
+        options.apply(&listener)?;
+        tcp.apply(&listener)?;
+        let listener = listener.bind((bind, port))?.listen(128)?;
 
         Ok(Self {
             socket: listener,
             connection: None,
+            replier: None,
+            outbox: vec![],
             buf: Vec::with_capacity(RECV_BUF),
+            framing,
+            pending: false,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring: false,
         })
     }
+
+    /// Reads connections with a multishot io_uring `recv` into provided buffers
+    pub fn set_uring(&mut self) -> Result<(), ProtoError> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            uring::probe()?;
+            self.uring = true;
+            Ok(())
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        Err(uring_unsupported())
+    }
 }
@@ -0,0 +1,309 @@
+//! Transport diagnostics of TCP connections from `TCP_INFO`
+//!
+//! TCP hides loss from the frames by retransmitting, these counters expose it.
+use std::fmt;
+#[cfg(target_os = "linux")]
+use std::{io, os::fd::AsRawFd};
+
+#[cfg(target_os = "linux")]
+use super::sockopt::getsockopt;
+use crate::rate::Bitrate;
+
+/// Level of MPTCP socket options, `SOL_MPTCP`
+#[cfg(target_os = "linux")]
+const SOL_MPTCP: libc::c_int = 284;
+
+/// `MPTCP_INFO`
+#[cfg(target_os = "linux")]
+const MPTCP_INFO: libc::c_int = 1;
+
+/// Connection fell back to TCP, as the peer doesn't take MPTCP, `MPTCP_INFO_FLAG_FALLBACK`
+#[cfg(target_os = "linux")]
+const MPTCP_FALLBACK: u32 = 1;
+
+/// State of a connection at some moment, counters are since it was established
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpInfo {
+    /// Segments retransmitted
+    pub retransmits: u32,
+    /// Segments currently considered lost
+    pub lost: u32,
+    /// Smoothed round trip time, us
+    pub rtt: u32,
+    /// Round trip time variation, us
+    pub rttvar: u32,
+    /// Congestion window, segments
+    pub cwnd: u32,
+    /// Segments the connection expects to arrive out of order
+    pub reordering: u32,
+    /// Rate of the most recent delivery, bytes per second
+    pub delivery_rate: u64,
+    /// Subflows in use by an MPTCP connection, the initial one included
+    pub subflows: Option<u8>,
+}
+
+/// `struct tcp_info` of the kernel up to `tcpi_delivery_rate`, older kernels fill in less
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct RawTcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    wscale: u8,
+    app_limited: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+    pacing_rate: u64,
+    max_pacing_rate: u64,
+    bytes_acked: u64,
+    bytes_received: u64,
+    segs_out: u32,
+    segs_in: u32,
+    notsent_bytes: u32,
+    min_rtt: u32,
+    data_segs_in: u32,
+    data_segs_out: u32,
+    delivery_rate: u64,
+}
+
+/// `struct mptcp_info` of the kernel up to `mptcpi_subflows_total`
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct RawMptcpInfo {
+    subflows: u8,
+    add_addr_signal: u8,
+    add_addr_accepted: u8,
+    subflows_max: u8,
+    add_addr_signal_max: u8,
+    add_addr_accepted_max: u8,
+    flags: u32,
+    token: u32,
+    write_seq: u64,
+    snd_una: u64,
+    rcv_nxt: u64,
+    local_addr_used: u8,
+    local_addr_max: u8,
+    csum_enabled: u8,
+    retransmits: u32,
+    bytes_retrans: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    bytes_acked: u64,
+    /// Since Linux 6.10, older kernels only count the additional subflows
+    subflows_total: u8,
+}
+
+/// Subflows in use if `socket` is of an MPTCP connection which didn't fall back to TCP
+#[cfg(target_os = "linux")]
+fn mptcp_subflows(socket: &impl AsRawFd) -> Option<u8> {
+    let mut raw = RawMptcpInfo::default();
+    getsockopt(socket.as_raw_fd(), SOL_MPTCP, MPTCP_INFO, &mut raw).ok()?;
+    if raw.flags & MPTCP_FALLBACK != 0 {
+        return None;
+    }
+    Some(match raw.subflows_total {
+        0 => raw.subflows.saturating_add(1),
+        total => total,
+    })
+}
+
+impl TcpInfo {
+    /// Samples `TCP_INFO` of a connected `socket`
+    #[cfg(target_os = "linux")]
+    pub(super) fn of(socket: &impl AsRawFd) -> io::Result<Self> {
+        let mut raw = RawTcpInfo::default();
+        getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut raw,
+        )?;
+        Ok(Self {
+            retransmits: raw.total_retrans,
+            lost: raw.lost,
+            rtt: raw.rtt,
+            rttvar: raw.rttvar,
+            cwnd: raw.snd_cwnd,
+            reordering: raw.reordering,
+            delivery_rate: raw.delivery_rate,
+            subflows: mptcp_subflows(socket),
+        })
+    }
+}
+
+/// Settings of a connection as the kernel applied them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TcpSettings {
+    /// Congestion control algorithm
+    pub congestion: String,
+    /// Maximum segment size, bytes
+    pub mss: u32,
+    pub nodelay: bool,
+    /// Unsent bytes below which the socket takes more writes, 0 for the system default
+    pub notsent_lowat: u32,
+    /// The socket is of MPTCP
+    pub multipath: bool,
+}
+
+impl TcpSettings {
+    /// Reads the settings back from `socket`
+    #[cfg(target_os = "linux")]
+    pub(super) fn of(socket: &impl AsRawFd) -> io::Result<Self> {
+        let fd = socket.as_raw_fd();
+        let mut name = [0_u8; 16];
+        let len = getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_CONGESTION, &mut name)?;
+        let name = &name[..len.min(name.len())];
+        let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+        let int = |option| {
+            let mut value: libc::c_int = 0;
+            getsockopt(fd, libc::IPPROTO_TCP, option, &mut value).map(|_| value)
+        };
+        Ok(Self {
+            congestion: String::from_utf8_lossy(name).into_owned(),
+            mss: int(libc::TCP_MAXSEG)? as u32,
+            nodelay: int(libc::TCP_NODELAY)? != 0,
+            notsent_lowat: int(libc::TCP_NOTSENT_LOWAT)? as u32,
+            multipath: {
+                let mut protocol: libc::c_int = 0;
+                getsockopt(fd, libc::SOL_SOCKET, libc::SO_PROTOCOL, &mut protocol)?;
+                protocol == libc::IPPROTO_MPTCP
+            },
+        })
+    }
+}
+
+impl fmt::Display for TcpSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.multipath {
+            write!(f, "multipath, ")?;
+        }
+        write!(
+            f,
+            "{} congestion control, MSS {}",
+            self.congestion, self.mss
+        )?;
+        if self.nodelay {
+            write!(f, ", no delay")?;
+        }
+        if self.notsent_lowat != 0 {
+            write!(f, ", not sent low water mark {} bytes", self.notsent_lowat)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TcpInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} retransmits, {} lost, RTT {:.2}ms ±{:.2}ms, cwnd {}, reordering {}, delivery rate {}",
+            self.retransmits,
+            self.lost,
+            f64::from(self.rtt) / 1000.,
+            f64::from(self.rttvar) / 1000.,
+            self.cwnd,
+            self.reordering,
+            Bitrate(self.delivery_rate.saturating_mul(8))
+        )?;
+        if let Some(subflows) = self.subflows {
+            write!(f, ", {subflows} MPTCP subflows")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[test]
+    fn test_of() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut receiver, _) = listener.accept().unwrap();
+        sender.write_all(&[1; 100_000]).unwrap();
+        receiver.read_exact(&mut [0; 100_000]).unwrap();
+        let info = TcpInfo::of(&sender).unwrap();
+        assert!(info.cwnd > 0 && info.rtt > 0);
+        assert_eq!(info.retransmits, 0);
+        assert_eq!(info.subflows, None);
+    }
+
+    #[test]
+    fn test_settings() {
+        use crate::protocols::TcpOptions;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = TcpOptions {
+            congestion: Some("reno".into()),
+            nodelay: true,
+            notsent_lowat: Some(16384),
+            mss: Some(1000),
+            ..Default::default()
+        };
+        options.apply(&listener).unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let settings = TcpSettings::of(&accepted).unwrap();
+        assert_eq!(settings.congestion, "reno");
+        assert!(settings.nodelay && settings.mss <= 1000);
+        assert_eq!(settings.notsent_lowat, 16384);
+        assert!(!settings.multipath);
+        assert!(!TcpSettings::of(&stream).unwrap().nodelay);
+    }
+
+    #[test]
+    fn test_mptcp() {
+        use net2::TcpBuilder;
+        use std::os::fd::FromRawFd;
+        // MPTCP may be compiled out or disabled by net.mptcp.enabled
+        let mptcp = || {
+            let kind = libc::SOCK_STREAM | libc::SOCK_CLOEXEC;
+            let fd = unsafe { libc::socket(libc::AF_INET, kind, libc::IPPROTO_MPTCP) };
+            (fd >= 0).then(|| unsafe { TcpBuilder::from_raw_fd(fd) })
+        };
+        let (Some(listener), Some(sender)) = (mptcp(), mptcp()) else {
+            eprintln!("MPTCP is unavailable, skipping");
+            return;
+        };
+        let listener = listener.bind("127.0.0.1:0").unwrap().listen(1).unwrap();
+        let mut sender = sender.connect(listener.local_addr().unwrap()).unwrap();
+        let (mut receiver, _) = listener.accept().unwrap();
+        sender.write_all(&[1; 100_000]).unwrap();
+        receiver.read_exact(&mut [0; 100_000]).unwrap();
+        assert!(TcpSettings::of(&sender).unwrap().multipath);
+        assert!(TcpSettings::of(&receiver).unwrap().multipath);
+        let info = TcpInfo::of(&sender).unwrap();
+        assert!(matches!(info.subflows, Some(subflows) if subflows >= 1));
+    }
+}
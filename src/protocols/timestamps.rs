@@ -0,0 +1,236 @@
+//! Packet timestamps taken by the kernel, Linux only
+//!
+//! `SO_TIMESTAMPNS` stamps received datagrams as they arrive at the socket,
+//! free of the scheduling delay before the receiving loop sees them.
+//! `SO_TIMESTAMPING` reports when sent datagrams enter the qdisc and when they
+//! reach the driver, through the socket's error queue.
+use std::{
+    collections::VecDeque,
+    io,
+    mem::size_of,
+    os::fd::AsRawFd,
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::sockopt::setsockopt;
+
+/// Stamp taken as the datagram entered the qdisc, `SCM_TSTAMP_SCHED`
+const TSTAMP_SCHED: u32 = 1;
+
+/// Stamp taken as the datagram reached the driver, `SCM_TSTAMP_SND`
+const TSTAMP_SND: u32 = 0;
+
+/// Datagrams whose qdisc stamp waits for the driver's, the oldest are forgotten past it
+const PENDING: usize = 4096;
+
+/// Room for the stamps and the extended error carrying the datagram's id
+type Control = [u64; 16];
+
+/// Stamps received datagrams with `SO_TIMESTAMPNS`
+pub fn enable_rx(socket: &impl AsRawFd) -> io::Result<()> {
+    setsockopt(
+        socket.as_raw_fd(),
+        libc::SOL_SOCKET,
+        libc::SO_TIMESTAMPNS,
+        &1_i32,
+    )
+}
+
+/// Wall clock time of a `timespec` stamp
+pub fn wall_time(ts: &libc::timespec) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Both clocks read at once, to place wall clock stamps of the kernel on the monotonic one
+pub struct Epoch {
+    now: Instant,
+    wall: SystemTime,
+}
+
+impl Epoch {
+    pub fn now() -> Self {
+        Self {
+            now: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// Monotonic time of `stamp`, none if it is from the future
+    pub fn instant(&self, stamp: SystemTime) -> Option<Instant> {
+        self.now.checked_sub(self.wall.duration_since(stamp).ok()?)
+    }
+}
+
+/// Time sent datagrams spend in the qdisc, measured with transmit stamps
+pub struct TxStamps {
+    /// Qdisc stamps and ids of the datagrams, oldest first
+    scheduled: VecDeque<(u32, SystemTime)>,
+    control: Box<Control>,
+}
+
+impl TxStamps {
+    /// Enables software transmit stamps of `socket`, datagrams are numbered from 0
+    pub fn enable(socket: &impl AsRawFd) -> io::Result<Self> {
+        let flags = libc::SOF_TIMESTAMPING_TX_SCHED
+            | libc::SOF_TIMESTAMPING_TX_SOFTWARE
+            | libc::SOF_TIMESTAMPING_SOFTWARE
+            | libc::SOF_TIMESTAMPING_OPT_ID
+            | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+        setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags,
+        )?;
+        Ok(Self {
+            scheduled: VecDeque::new(),
+            control: Box::new([0; 16]),
+        })
+    }
+
+    /// Drains the error queue of `socket`, passing the qdisc delay of every datagram to `delay`
+    pub fn poll(&mut self, socket: &impl AsRawFd, delay: &mut dyn FnMut(Duration)) {
+        while let Some(message) = self.next(socket) {
+            if let Some((kind, id, stamp)) = message {
+                self.stamped(kind, id, stamp, delay);
+            }
+        }
+    }
+
+    /// Pairs the stamps of a datagram, they come in the order the datagrams were sent
+    fn stamped(&mut self, kind: u32, id: u32, stamp: SystemTime, delay: &mut dyn FnMut(Duration)) {
+        match kind {
+            TSTAMP_SCHED => {
+                if self.scheduled.len() >= PENDING {
+                    self.scheduled.pop_front();
+                }
+                self.scheduled.push_back((id, stamp));
+            }
+            TSTAMP_SND => {
+                // older datagrams without the driver's stamp are forgotten, ids wrap
+                while let Some(&(first, scheduled)) = self.scheduled.front() {
+                    if (id.wrapping_sub(first) as i32) < 0 {
+                        break;
+                    }
+                    self.scheduled.pop_front();
+                    if first == id {
+                        delay(stamp.duration_since(scheduled).unwrap_or_default());
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Kind, id and software stamp of the next message of the error queue
+    ///
+    /// `None` once the queue is empty, `Some(None)` for a message which isn't a stamp.
+    fn next(&mut self, socket: &impl AsRawFd) -> Option<Option<(u32, u32, SystemTime)>> {
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_control = self.control.as_mut_ptr().cast();
+        msg.msg_controllen = size_of::<Control>() as _;
+        let flags = libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT;
+        if unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) } < 0 {
+            return None;
+        }
+        let (mut stamp, mut error) = (None, None);
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            match (header.cmsg_level, header.cmsg_type) {
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING) => {
+                    // software, deprecated and hardware stamps, only the first is set
+                    let ts = unsafe { std::ptr::read_unaligned(data as *const libc::timespec) };
+                    stamp = Some(wall_time(&ts));
+                }
+                (libc::SOL_IP, libc::IP_RECVERR) => {
+                    let ee =
+                        unsafe { std::ptr::read_unaligned(data as *const libc::sock_extended_err) };
+                    if ee.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING {
+                        error = Some((ee.ee_info, ee.ee_data));
+                    }
+                }
+                _ => {}
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+        Some(
+            error
+                .zip(stamp)
+                .map(|((kind, id), stamp)| (kind, id, stamp)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::mmsg;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_epoch() {
+        let epoch = Epoch::now();
+        let stamp = SystemTime::now() - Duration::from_millis(5);
+        let instant = epoch.instant(stamp).unwrap();
+        assert!(epoch.now.duration_since(instant) <= Duration::from_millis(5));
+        assert_eq!(
+            epoch.instant(SystemTime::now() + Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rx_stamps() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        enable_rx(&receiver).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let before = SystemTime::now();
+        sender
+            .send_to(b"frame", receiver.local_addr().unwrap())
+            .unwrap();
+        let mut buf = [0; 64];
+        let received = mmsg::recv_batch(&receiver, &mut buf, 64).unwrap();
+        let stamp = received[0].received.unwrap();
+        assert!(stamp >= before && stamp <= SystemTime::now());
+    }
+
+    #[test]
+    fn test_tx_stamps() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        let mut stamps = TxStamps::enable(&sender).unwrap();
+        for _ in 0..10 {
+            sender.send(b"frame").unwrap();
+        }
+        let mut delays = 0;
+        stamps.poll(&sender, &mut |delay| {
+            assert!(delay < Duration::from_secs(1));
+            delays += 1;
+        });
+        assert_eq!(delays, 10);
+    }
+
+    #[test]
+    fn test_evicts_oldest_stamps() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut stamps = TxStamps::enable(&socket).unwrap();
+        let start = SystemTime::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut delays = vec![];
+        let mut delay = |delay: Duration| delays.push(delay.as_millis());
+        for id in 0..=PENDING as u32 {
+            stamps.stamped(TSTAMP_SCHED, id, at(id.into()), &mut delay);
+        }
+        // the first one is gone, the second is still there
+        stamps.stamped(TSTAMP_SND, 0, at(5), &mut delay);
+        stamps.stamped(TSTAMP_SND, 1, at(5), &mut delay);
+        // the driver's stamp of 2 went missing
+        stamps.stamped(TSTAMP_SND, 3, at(10), &mut delay);
+        assert_eq!(delays, [4, 7]);
+        assert_eq!(stamps.scheduled.front().map(|(id, _)| *id), Some(4));
+    }
+}
@@ -1,55 +1,246 @@
 use net2::UdpBuilder;
 use std::{
     net::{Ipv4Addr, SocketAddr, UdpSocket},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use super::{ProtoError, Receiver, Sender, RECV_BUF};
+#[cfg(not(target_os = "linux"))]
+use super::unsupported;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use super::uring::{DatagramReader, Writer};
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+use super::uring_unsupported;
+use super::{is_timeout, ProtoError, Receiver, Sender, SocketOptions, RECV_BUF};
+#[cfg(target_os = "linux")]
+use super::{
+    mmsg,
+    pacing::{self, TxTime},
+    send_each,
+    timestamps::{self, Epoch, TxStamps},
+};
+#[cfg(target_os = "linux")]
+use crate::rate::Rate;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use std::os::fd::AsRawFd;
 
 pub struct UdpSender {
     socket: UdpSocket,
+    buf: Box<[u8]>,
+    finished: bool,
+    /// Datagrams per `sendmmsg`
+    batch: usize,
+    /// Launch time schedule, when pacing is left to the qdisc
+    #[cfg(target_os = "linux")]
+    txtime: Option<TxTime>,
+    /// `SO_SNDBUF` set by the user, kept by kernel pacing
+    #[cfg(target_os = "linux")]
+    send_buffer: Option<u32>,
+    /// Runs of equally sized frames in a batch are segmented by the kernel
+    #[cfg(target_os = "linux")]
+    gso: bool,
+    /// Transmit stamps of the datagrams, once enabled
+    #[cfg(target_os = "linux")]
+    tx_stamps: Option<TxStamps>,
+    /// Frames go through io_uring instead
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: Option<Writer>,
 }
 
 impl UdpSender {
-    pub fn new(peer: Ipv4Addr, port: u16, bind: Ipv4Addr) -> anyhow::Result<Self> {
-        let socket = if peer.is_multicast() {
-            let socket = UdpBuilder::new_v4()?;
+    /// `batch` datagrams are sent with one syscall where supported
+    pub fn new(
+        peer: Ipv4Addr,
+        port: u16,
+        bind: Ipv4Addr,
+        batch: usize,
+        options: &SocketOptions,
+    ) -> anyhow::Result<Self> {
+        let socket = UdpBuilder::new_v4()?;
+        if peer.is_multicast() {
             // https://stackoverflow.com/questions/14388706/how-do-so-reuseaddr-and-so-reuseport-differ/14388707#14388707
             socket.reuse_address(true)?;
-            let socket = socket.bind((bind, 0))?;
-            socket.set_multicast_ttl_v4(1)?;
-            socket.connect((peer, port))?;
-            socket
-        } else {
-            let socket = UdpSocket::bind((bind, 0))?;
-            socket.connect((peer, port))?;
-            socket
-        };
-        Ok(Self { socket })
+        }
+        options.apply(&socket)?;
+        let socket = socket.bind((bind, 0))?;
+        if peer.is_multicast() {
+            socket.set_multicast_ttl_v4(options.multicast_ttl.unwrap_or(1).into())?;
+        }
+        socket.connect((peer, port))?;
+        Ok(Self {
+            socket,
+            buf: vec![0; RECV_BUF].into_boxed_slice(),
+            finished: false,
+            batch,
+            #[cfg(target_os = "linux")]
+            txtime: None,
+            #[cfg(target_os = "linux")]
+            send_buffer: options.send_buffer,
+            #[cfg(target_os = "linux")]
+            gso: false,
+            #[cfg(target_os = "linux")]
+            tx_stamps: None,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring: None,
+        })
+    }
+
+    /// Sends batches with UDP segmentation offload
+    pub fn set_gso(&mut self) -> Result<(), ProtoError> {
+        #[cfg(target_os = "linux")]
+        {
+            mmsg::probe_gso(&self.socket)?;
+            self.gso = true;
+            Ok(())
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(unsupported("UDP_SEGMENT"))
+    }
+
+    /// Sends through io_uring, from a registered buffer
+    pub fn set_uring(&mut self) -> Result<(), ProtoError> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            self.uring = Some(Writer::new()?);
+            Ok(())
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        Err(uring_unsupported())
     }
 }
 
 impl Sender for UdpSender {
     fn send(&mut self, data: &[u8]) -> Result<(), ProtoError> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(writer) = &mut self.uring {
+            writer.send_batch(self.socket.as_raw_fd(), &[data])?;
+            return Ok(());
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(txtime) = &mut self.txtime {
+            txtime.send(&self.socket, data)?;
+            return Ok(());
+        }
         self.socket.send(data)?;
         Ok(())
     }
+
+    #[cfg(target_os = "linux")]
+    fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize, ProtoError> {
+        #[cfg(feature = "io-uring")]
+        if let Some(writer) = &mut self.uring {
+            return Ok(writer.send_batch(self.socket.as_raw_fd(), frames)?);
+        }
+        // every datagram carries its own launch time
+        if self.txtime.is_some() {
+            return send_each(self, frames);
+        }
+        Ok(mmsg::send_batch(&self.socket, frames, self.gso)?)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn batch_size(&self) -> usize {
+        #[cfg(feature = "io-uring")]
+        if let Some(writer) = &self.uring {
+            return self.batch.clamp(1, writer.batch_size());
+        }
+        match self.txtime {
+            Some(_) => 1,
+            None => self.batch.clamp(1, mmsg::MAX_BATCH),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), ProtoError> {
+        self.finished = true;
+        self.socket.send(&[0])?;
+        Ok(())
+    }
+
+    fn recv_reply(&mut self, timeout: Duration) -> Result<Option<&[u8]>, ProtoError> {
+        if timeout.is_zero() {
+            self.socket.set_nonblocking(true)?;
+        } else {
+            self.socket.set_read_timeout(Some(timeout))?;
+        }
+        let res = self.socket.recv(&mut self.buf);
+        self.socket.set_nonblocking(false)?;
+        match res {
+            Ok(size) => Ok(Some(&self.buf[..size])),
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_pacing_rate(&mut self, rate: &Rate) -> Result<(), ProtoError> {
+        pacing::set_max_pacing_rate(&self.socket, rate, self.send_buffer)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_txtime(&mut self, rate: &Rate) -> Result<(), ProtoError> {
+        // launch times need a control message with every datagram
+        #[cfg(feature = "io-uring")]
+        if self.uring.is_some() {
+            return Err(super::unsupported("SO_TXTIME with io_uring"));
+        }
+        self.txtime = Some(TxTime::enable(&self.socket, rate, self.send_buffer)?);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_tx_timestamps(&mut self) -> Result<(), ProtoError> {
+        self.tx_stamps = Some(TxStamps::enable(&self.socket)?);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tx_delays(&mut self, delay: &mut dyn FnMut(Duration)) {
+        if let Some(stamps) = &mut self.tx_stamps {
+            stamps.poll(&self.socket, delay);
+        }
+    }
 }
 
 impl Drop for UdpSender {
     fn drop(&mut self) {
-        let _x = self.socket.send(&[0]);
+        if !self.finished {
+            let _x = self.socket.send(&[0]);
+        }
     }
 }
 
+/// Takes frames of any peer with their sender and arrival time
+type PeerFrames<'a> = dyn FnMut(SocketAddr, &[u8], Option<Instant>) + 'a;
+
 pub struct UdpReceiver {
     socket: UdpSocket,
     buf: Box<[u8]>,
     client: Option<SocketAddr>,
+    /// Slots of `slot` bytes for `recvmmsg`, a single one when receiving one by one
+    #[cfg(target_os = "linux")]
+    batch_buf: Box<[u8]>,
+    /// Longest datagram a slot takes, longer ones are truncated
+    #[cfg(target_os = "linux")]
+    slot: usize,
+    /// Datagrams dropped by the socket, counted with `SO_RXQ_OVFL`
+    #[cfg(target_os = "linux")]
+    dropped: Option<u64>,
+    /// Last value of the kernel's wrapping drop counter
+    #[cfg(target_os = "linux")]
+    drops_seen: u32,
+    /// Truncated control messages were reported, it is done once
+    #[cfg(target_os = "linux")]
+    truncation_reported: bool,
+    /// Datagrams come from a multishot io_uring receive instead
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: Option<DatagramReader>,
 }
 
 impl Receiver for UdpReceiver {
-    fn recv<'a>(&mut self) -> Result<&[u8], ProtoError> {
+    /// Receives a frame at a time
+    ///
+    /// With GRO enabled only `recv_batch` splits the coalesced datagrams.
+    fn recv(&mut self) -> Result<&[u8], ProtoError> {
         if let Some(client) = self.client {
             let (size, addr) = self.socket.recv_from(&mut self.buf)?;
             if client != addr {
@@ -59,33 +250,245 @@ impl Receiver for UdpReceiver {
                 self.client = None;
                 return Err(ProtoError::Disconnected(addr));
             }
-            return Ok(&self.buf[..size]);
+            Ok(&self.buf[..size])
         } else {
-            let (_size, addr) = self.socket.peek_from(&mut self.buf)?;
+            let (size, addr) = self.socket.peek_from(&mut self.buf)?;
+            if size == 1 && self.buf[0] == 0 {
+                // the session it ends is over already
+                self.socket.recv_from(&mut self.buf)?;
+                return Err(ProtoError::Ended(addr));
+            }
             self.client = Some(addr);
-            return Err(ProtoError::Connected(addr));
+            Err(ProtoError::Connected(addr))
         }
     }
+
+    #[cfg(target_os = "linux")]
+    fn recv_batch(
+        &mut self,
+        handle: &mut dyn FnMut(&[u8], Option<Instant>),
+    ) -> Result<usize, ProtoError> {
+        #[cfg(feature = "io-uring")]
+        if let Some(reader) = &mut self.uring {
+            return reader.recv_batch(self.socket.as_raw_fd(), &mut self.client, handle);
+        }
+        let Some(client) = self.client else {
+            handle(self.recv()?, None);
+            return Ok(1);
+        };
+        let mut conflicting = None;
+        let mut ended = false;
+        let mut frames = 0;
+        self.recv_from_batch(&mut |addr, frame, received| {
+            if addr != client {
+                conflicting = Some(addr);
+            } else if ended {
+                // datagrams after the end of the session are dropped
+            } else if frame == [0] {
+                ended = true;
+            } else {
+                handle(frame, received);
+                frames += 1;
+            }
+        })?;
+        if ended {
+            self.client = None;
+            return Err(ProtoError::Disconnected(client));
+        }
+        match conflicting {
+            Some(addr) => Err(ProtoError::ConflictingClient(addr)),
+            None => Ok(frames),
+        }
+    }
+
+    fn reply(&mut self, peer: SocketAddr, data: &[u8]) -> Result<(), ProtoError> {
+        self.socket.send_to(data, peer)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn dropped(&self) -> Option<u64> {
+        self.dropped
+    }
 }
 
 impl UdpReceiver {
-    pub fn new(peer: Ipv4Addr, port: u16, bind: Ipv4Addr) -> anyhow::Result<Self> {
-        let socket = if peer.is_multicast() {
-            let socket = UdpBuilder::new_v4()?;
+    /// Up to `batch` datagrams are received with one syscall where supported,
+    /// into slots of `mtu` bytes, as no datagram of the link is longer
+    pub fn new(
+        peer: Ipv4Addr,
+        port: u16,
+        bind: Ipv4Addr,
+        batch: usize,
+        mtu: u16,
+        options: &SocketOptions,
+    ) -> anyhow::Result<Self> {
+        Self::bind(peer, port, bind, batch, mtu, options, false)
+    }
+
+    /// Receiver sharing the port with others through `SO_REUSEPORT`
+    ///
+    /// The kernel spreads flows between the sockets by their addresses.
+    pub fn new_shared(
+        peer: Ipv4Addr,
+        port: u16,
+        bind: Ipv4Addr,
+        batch: usize,
+        mtu: u16,
+        options: &SocketOptions,
+    ) -> anyhow::Result<Self> {
+        Self::bind(peer, port, bind, batch, mtu, options, true)
+    }
+
+    fn bind(
+        peer: Ipv4Addr,
+        port: u16,
+        bind: Ipv4Addr,
+        batch: usize,
+        mtu: u16,
+        options: &SocketOptions,
+        shared: bool,
+    ) -> anyhow::Result<Self> {
+        let socket = UdpBuilder::new_v4()?;
+        if peer.is_multicast() || shared {
             socket.reuse_address(true)?;
-            let socket = socket.bind((bind, port))?;
+        }
+        if shared {
+            #[cfg(unix)]
+            net2::unix::UnixUdpBuilderExt::reuse_port(&socket, true)?;
+            #[cfg(not(unix))]
+            Err(unsupported("SO_REUSEPORT"))?;
+        }
+        options.apply(&socket)?;
+        let socket = socket.bind((bind, port))?;
+        if peer.is_multicast() {
             socket.join_multicast_v4(&peer, &bind)?;
-            socket
-        } else {
-            let socket = UdpSocket::bind(&(bind, port))?;
-            socket
-        };
+        }
         socket.set_read_timeout(Some(Duration::from_secs(1)))?;
 
+        // only `recvmmsg` tells the drop counter and stamps, so there is at least one slot
+        #[cfg(target_os = "linux")]
+        {
+            mmsg::enable_drop_counter(&socket)?;
+            timestamps::enable_rx(&socket)?;
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        let _ = (batch, mtu);
+        #[cfg(target_os = "linux")]
+        let slot = usize::from(mtu).max(1);
         Ok(Self {
             socket,
             buf: vec![0; RECV_BUF].into_boxed_slice(),
             client: None,
+            #[cfg(target_os = "linux")]
+            batch_buf: vec![0; batch.clamp(1, mmsg::MAX_BATCH) * slot].into_boxed_slice(),
+            #[cfg(target_os = "linux")]
+            slot,
+            #[cfg(target_os = "linux")]
+            dropped: Some(0),
+            #[cfg(target_os = "linux")]
+            drops_seen: 0,
+            #[cfg(target_os = "linux")]
+            truncation_reported: false,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring: None,
         })
     }
+
+    /// Receives frames of any peer, passing each to `handle` with its sender and arrival time
+    ///
+    /// Doesn't track the client, end of stream markers are passed on as well.
+    pub fn recv_from_batch(&mut self, handle: &mut PeerFrames<'_>) -> Result<usize, ProtoError> {
+        #[cfg(target_os = "linux")]
+        {
+            let received = mmsg::recv_batch(&self.socket, &mut self.batch_buf, self.slot)?;
+            let epoch = Epoch::now();
+            let mut frames = 0;
+            for (slot, datagram) in self.batch_buf.chunks_exact(self.slot).zip(&received) {
+                if let (Some(dropped), Some(drops)) = (&mut self.dropped, datagram.drops) {
+                    *dropped += u64::from(drops.wrapping_sub(self.drops_seen));
+                    self.drops_seen = drops;
+                }
+                if datagram.truncated && !self.truncation_reported {
+                    // GRO coalesced datagrams would pass as single frames
+                    eprintln!(
+                        "Control messages of datagrams truncated: \
+                         GRO segments, drop counts or stamps may be missing"
+                    );
+                    self.truncation_reported = true;
+                }
+                let data = &slot[..datagram.len];
+                let arrived = datagram.received.and_then(|stamp| epoch.instant(stamp));
+                // GRO coalesced frames, the last one may be shorter
+                for frame in data.chunks(datagram.segment.unwrap_or(data.len()).max(1)) {
+                    handle(datagram.addr, frame, arrived);
+                    frames += 1;
+                }
+            }
+            Ok(frames)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let (size, addr) = self.socket.recv_from(&mut self.buf)?;
+            handle(addr, &self.buf[..size], None);
+            Ok(1)
+        }
+    }
+
+    /// Lets the kernel coalesce received datagrams with GRO, into slots of the largest size
+    pub fn set_gro(&mut self) -> Result<(), ProtoError> {
+        #[cfg(target_os = "linux")]
+        {
+            mmsg::enable_gro(&self.socket)?;
+            let slots = self.batch_buf.len() / self.slot;
+            self.batch_buf = vec![0; slots * RECV_BUF].into_boxed_slice();
+            self.slot = RECV_BUF;
+            Ok(())
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(unsupported("UDP_GRO"))
+    }
+
+    /// Receives with a multishot io_uring `recvmsg` into provided buffers
+    pub fn set_uring(&mut self) -> Result<(), ProtoError> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            self.uring = Some(DatagramReader::new()?);
+            // its completions don't carry the drop counter
+            self.dropped = None;
+            Ok(())
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        Err(uring_unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_end() {
+        let options = SocketOptions::default();
+        let mut receiver = UdpReceiver::new(
+            Ipv4Addr::LOCALHOST,
+            0,
+            Ipv4Addr::LOCALHOST,
+            1,
+            1500,
+            &options,
+        )
+        .unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .connect(receiver.socket.local_addr().unwrap())
+            .unwrap();
+        let addr = sender.local_addr().unwrap();
+        sender.send(&[0]).unwrap();
+        sender.send(b"frame").unwrap();
+        assert!(matches!(receiver.recv(), Err(ProtoError::Ended(peer)) if peer == addr));
+        assert!(matches!(receiver.recv(), Err(ProtoError::Connected(peer)) if peer == addr));
+        assert_eq!(receiver.recv().unwrap(), b"frame");
+    }
 }
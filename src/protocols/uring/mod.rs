@@ -0,0 +1,407 @@
+//! io_uring transports, Linux only with the `io-uring` feature
+//!
+//! Frames are copied into a registered buffer and written with linked
+//! `WRITE_FIXED` requests, a batch per `io_uring_enter`. Receiving keeps a
+//! multishot request armed, which fills buffers provided to the kernel and
+//! completes once per datagram or stream chunk.
+mod ring;
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Read},
+    mem::size_of,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpStream},
+    os::fd::{AsRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use ring::{BufRing, Cqe, RecvMsgOut, Ring, Sqe};
+
+use super::{ProtoError, RECV_BUF};
+
+/// Submission queue size, the most frames written with one call
+const ENTRIES: u32 = 256;
+
+/// Registered buffer frames are copied into before writing
+const SEND_BUF: usize = 4 << 20;
+
+/// Buffers provided for receiving
+const RECV_BUFFERS: u16 = 128;
+
+/// Buffer group of the provided buffers
+const GROUP: u16 = 0;
+
+/// How long a receive waits before letting the caller check for shutdown
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Room for the `recvmsg` header and the sender's address before the payload
+const RECVMSG_HEADER: usize = size_of::<RecvMsgOut>() + size_of::<libc::sockaddr_in>();
+
+/// Checks that the kernel has everything the readers need
+pub fn probe() -> io::Result<()> {
+    Multishot::new(RECV_BUF).map(drop)
+}
+
+/// Writes frames to a connected socket
+pub struct Writer {
+    ring: Ring,
+    buf: Box<[u8]>,
+}
+
+impl Writer {
+    pub fn new() -> io::Result<Self> {
+        let ring = Ring::new(ENTRIES)?;
+        let mut buf = vec![0; SEND_BUF].into_boxed_slice();
+        ring.register_buffer(&mut buf)?;
+        Ok(Self { ring, buf })
+    }
+
+    /// Most frames a single `send_batch` takes
+    pub fn batch_size(&self) -> usize {
+        self.ring.entries() as usize
+    }
+
+    /// Writes `frames` to `fd` in order, returns how many were written before an error
+    ///
+    /// Writes are linked, each starts after the previous one completes in full,
+    /// so a failure cancels the rest as with `sendmmsg`. The error is returned
+    /// if the first frame fails, later frames are left for the next call.
+    /// A write cut short is completed.
+    pub fn send_batch(&mut self, fd: RawFd, frames: &[&[u8]]) -> io::Result<usize> {
+        let mut offset = 0;
+        let mut writes = Vec::with_capacity(frames.len().min(self.batch_size()));
+        for frame in frames.iter().take(self.batch_size()) {
+            if offset + frame.len() > self.buf.len() {
+                break;
+            }
+            self.buf[offset..offset + frame.len()].copy_from_slice(frame);
+            writes.push((offset, frame.len()));
+            offset += frame.len();
+        }
+        for (i, (offset, len)) in writes.iter().enumerate() {
+            let last = i + 1 == writes.len();
+            let sqe = self.write_sqe(fd, *offset, *len, i as u64, !last);
+            assert!(self.ring.push(sqe), "Submission queue holds a batch");
+        }
+        self.ring.submit_and_wait(writes.len() as u32, None)?;
+        let count = writes.len();
+        let mut results = vec![None; count];
+        while results.iter().any(Option::is_none) {
+            match self.ring.pop() {
+                Some(cqe) => results[cqe.user_data as usize] = Some(cqe),
+                None => self.ring.submit_and_wait(1, None)?,
+            }
+        }
+        for (i, (cqe, (offset, len))) in results.into_iter().flatten().zip(writes).enumerate() {
+            match cqe.result() {
+                Ok(written) if written == len => {}
+                Ok(written) => {
+                    // the rest of the chain was cancelled
+                    self.write_all(fd, offset + written, len - written)?;
+                    return Ok(i + 1);
+                }
+                Err(e) if i == 0 => return Err(e),
+                // the rest was cancelled, a lasting error comes up on the next call
+                Err(_) => return Ok(i),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Writes `len` bytes at `offset` of the registered buffer, however many writes it takes
+    fn write_all(&mut self, fd: RawFd, mut offset: usize, mut len: usize) -> io::Result<()> {
+        while len > 0 {
+            let sqe = self.write_sqe(fd, offset, len, 0, false);
+            assert!(self.ring.push(sqe), "Submission queue is empty");
+            let cqe = loop {
+                self.ring.submit_and_wait(1, None)?;
+                if let Some(cqe) = self.ring.pop() {
+                    break cqe;
+                }
+            };
+            let written = cqe.result()?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            offset += written;
+            len -= written;
+        }
+        Ok(())
+    }
+
+    fn write_sqe(&self, fd: RawFd, offset: usize, len: usize, user_data: u64, link: bool) -> Sqe {
+        Sqe {
+            opcode: ring::OP_WRITE_FIXED,
+            flags: if link { ring::SQE_IO_LINK } else { 0 },
+            fd,
+            // current position, sockets have none
+            off: u64::MAX,
+            addr: self.buf[offset..].as_ptr() as u64,
+            len: len as u32,
+            user_data,
+            buf_index: 0,
+            ..Default::default()
+        }
+    }
+}
+
+/// Completions of a multishot receive into provided buffers
+struct Multishot {
+    ring: Ring,
+    buffers: BufRing,
+    armed: bool,
+    pending: VecDeque<Cqe>,
+}
+
+impl Multishot {
+    fn new(buffer: usize) -> io::Result<Self> {
+        let ring = Ring::new(ENTRIES)?;
+        let buffers = BufRing::register(&ring, RECV_BUFFERS, buffer, GROUP)?;
+        Ok(Self {
+            ring,
+            buffers,
+            armed: false,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Next completion, arming `sqe` first unless it still is
+    ///
+    /// Returns `None` after `timeout` without one.
+    fn next(&mut self, sqe: Sqe, timeout: Option<Duration>) -> io::Result<Option<Cqe>> {
+        if self.pending.is_empty() {
+            if !self.armed {
+                self.armed = self.ring.push(sqe);
+            }
+            self.ring.submit_and_wait(1, timeout)?;
+            self.pending.extend(std::iter::from_fn(|| self.ring.pop()));
+        }
+        let Some(cqe) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        if !cqe.more() {
+            // out of buffers or failed, armed again next time
+            self.armed = false;
+        }
+        match cqe.result() {
+            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => Ok(None),
+            Err(e) => Err(e),
+            Ok(_) => Ok(Some(cqe)),
+        }
+    }
+}
+
+/// Receives datagrams with a multishot `recvmsg`
+pub struct DatagramReader {
+    multishot: Multishot,
+    /// Template telling the kernel how much room to leave for the address
+    msg: Box<libc::msghdr>,
+}
+
+impl DatagramReader {
+    pub fn new() -> io::Result<Self> {
+        let mut msg: Box<libc::msghdr> = Box::new(unsafe { std::mem::zeroed() });
+        msg.msg_namelen = size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        Ok(Self {
+            multishot: Multishot::new(RECVMSG_HEADER + RECV_BUF)?,
+            msg,
+        })
+    }
+
+    /// Passes datagrams from `client` to `handle`, the first sender becomes the client
+    ///
+    /// Frames received in the meantime are handled before an error is returned.
+    pub fn recv_batch(
+        &mut self,
+        fd: RawFd,
+        client: &mut Option<SocketAddr>,
+        handle: &mut dyn FnMut(&[u8], Option<Instant>),
+    ) -> Result<usize, ProtoError> {
+        let sqe = Sqe {
+            opcode: ring::OP_RECVMSG,
+            flags: ring::SQE_BUFFER_SELECT,
+            ioprio: ring::RECV_MULTISHOT,
+            fd,
+            addr: &*self.msg as *const libc::msghdr as u64,
+            buf_index: GROUP,
+            ..Default::default()
+        };
+        let mut frames = 0;
+        let mut conflicting = None;
+        // waits for the first completion, then takes the ones that came along
+        while let Some(cqe) = self.multishot.next(sqe, Some(RECV_TIMEOUT))? {
+            let Some(bid) = cqe.buffer() else {
+                continue;
+            };
+            let (addr, payload) = parse_recvmsg(self.multishot.buffers.get(bid));
+            match *client {
+                None if payload == [0] => {
+                    // end of a session which is over already
+                    self.multishot.buffers.recycle(bid);
+                    return Err(ProtoError::Ended(addr));
+                }
+                None => {
+                    // handled on the next call, with the client known
+                    *client = Some(addr);
+                    self.multishot.pending.push_front(cqe);
+                    return Err(ProtoError::Connected(addr));
+                }
+                Some(client) if client != addr => conflicting = Some(addr),
+                Some(peer) if payload == [0] => {
+                    // datagrams after the end of the session are dropped
+                    self.multishot.buffers.recycle(bid);
+                    *client = None;
+                    return Err(ProtoError::Disconnected(peer));
+                }
+                Some(_) => {
+                    // completions carry no stamps
+                    handle(payload, None);
+                    frames += 1;
+                }
+            }
+            self.multishot.buffers.recycle(bid);
+            if self.multishot.pending.is_empty() {
+                break;
+            }
+        }
+        match (conflicting, frames) {
+            (Some(addr), _) => Err(ProtoError::ConflictingClient(addr)),
+            (None, 0) => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+            (None, frames) => Ok(frames),
+        }
+    }
+}
+
+/// Sender's address and payload from a buffer filled by multishot `recvmsg`
+fn parse_recvmsg(buf: &[u8]) -> (SocketAddr, &[u8]) {
+    let out = unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const RecvMsgOut) };
+    let name = &buf[size_of::<RecvMsgOut>()..];
+    let addr = unsafe { std::ptr::read_unaligned(name.as_ptr() as *const libc::sockaddr_in) };
+    let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+    let addr = SocketAddrV4::new(ip, u16::from_be(addr.sin_port)).into();
+    let payload = &buf[RECVMSG_HEADER..];
+    (
+        addr,
+        &payload[..(out.payloadlen as usize).min(payload.len())],
+    )
+}
+
+/// Reads a stream with a multishot `recv`, the chunks go straight to the framing
+pub struct StreamReader {
+    multishot: Multishot,
+    stream: TcpStream,
+    /// Buffer being read, its read and end offsets
+    chunk: Option<(u16, usize, usize)>,
+}
+
+impl StreamReader {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        Ok(Self {
+            multishot: Multishot::new(RECV_BUF)?,
+            stream,
+            chunk: None,
+        })
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for StreamReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.chunk.is_none() {
+            let sqe = Sqe {
+                opcode: ring::OP_RECV,
+                flags: ring::SQE_BUFFER_SELECT,
+                ioprio: ring::RECV_MULTISHOT,
+                fd: self.stream.as_raw_fd(),
+                buf_index: GROUP,
+                ..Default::default()
+            };
+            let cqe = loop {
+                if let Some(cqe) = self.multishot.next(sqe, None)? {
+                    break cqe;
+                }
+            };
+            // no buffer at the end of the stream
+            let Some(bid) = cqe.buffer() else {
+                return Ok(&[]);
+            };
+            self.chunk = Some((bid, 0, cqe.res as usize));
+        }
+        let (bid, start, end) = self.chunk.expect("filled above");
+        Ok(&self.multishot.buffers.get(bid)[start..end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Some((bid, start, end)) = &mut self.chunk {
+            *start += amt;
+            if *start >= *end {
+                self.multishot.buffers.recycle(*bid);
+                self.chunk = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, UdpSocket};
+
+    #[test]
+    fn test_datagram_roundtrip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        let mut writer = Writer::new().unwrap();
+        let frames: [&[u8]; 3] = [b"first", b"second", &[0]];
+        assert_eq!(writer.send_batch(sender.as_raw_fd(), &frames).unwrap(), 3);
+
+        let mut reader = DatagramReader::new().unwrap();
+        let mut client = None;
+        let mut received = vec![];
+        let mut handle = |frame: &[u8], _| received.push(frame.to_vec());
+        let fd = receiver.as_raw_fd();
+        let res = reader.recv_batch(fd, &mut client, &mut handle);
+        assert!(
+            matches!(res, Err(ProtoError::Connected(addr)) if addr == sender.local_addr().unwrap())
+        );
+        let res = reader.recv_batch(fd, &mut client, &mut handle);
+        assert!(matches!(res, Err(ProtoError::Disconnected(_))));
+        assert_eq!(received, [b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(client, None);
+    }
+
+    #[test]
+    fn test_first_error_surfaces() {
+        // no destination for the datagrams
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut writer = Writer::new().unwrap();
+        let frames: [&[u8]; 2] = [b"first", b"second"];
+        assert!(writer.send_batch(socket.as_raw_fd(), &frames).is_err());
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut reader = StreamReader::new(listener.accept().unwrap().0).unwrap();
+        let mut writer = Writer::new().unwrap();
+        let frames: [&[u8]; 2] = [b"hello ", b"world\n"];
+        assert_eq!(writer.send_batch(stream.as_raw_fd(), &frames).unwrap(), 2);
+        drop(stream);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "hello world\n");
+        assert_eq!(reader.fill_buf().unwrap(), b"");
+    }
+}
@@ -0,0 +1,433 @@
+//! Minimal io_uring: the submission and completion rings, registered
+//! buffers and rings of buffers provided for receiving
+//!
+//! Only the parts of the kernel ABI the transports use are declared.
+use std::{
+    io,
+    mem::size_of,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicU16, AtomicU32, Ordering},
+    time::Duration,
+};
+
+pub const OP_WRITE_FIXED: u8 = 5;
+pub const OP_RECVMSG: u8 = 10;
+pub const OP_RECV: u8 = 27;
+
+/// Next request starts only after this one completes in full
+pub const SQE_IO_LINK: u8 = 1 << 2;
+/// Kernel picks the buffer from the group in `buf_index`
+pub const SQE_BUFFER_SELECT: u8 = 1 << 5;
+
+/// Receive request stays armed, completing once per arrival
+pub const RECV_MULTISHOT: u16 = 1 << 1;
+
+const CQE_F_BUFFER: u32 = 1;
+const CQE_F_MORE: u32 = 1 << 1;
+const CQE_BUFFER_SHIFT: u32 = 16;
+
+const FEAT_SINGLE_MMAP: u32 = 1;
+const FEAT_EXT_ARG: u32 = 1 << 8;
+
+const ENTER_GETEVENTS: u32 = 1;
+const ENTER_EXT_ARG: u32 = 1 << 3;
+
+const OFF_SQ_RING: libc::off_t = 0;
+const OFF_SQES: libc::off_t = 0x1000_0000;
+
+const REGISTER_BUFFERS: libc::c_uint = 0;
+const REGISTER_PBUF_RING: libc::c_uint = 22;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqOffsets,
+    cq_off: CqOffsets,
+}
+
+/// Submission queue entry
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Sqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    pub off: u64,
+    pub addr: u64,
+    pub len: u32,
+    pub op_flags: u32,
+    pub user_data: u64,
+    /// Registered buffer, or buffer group with `SQE_BUFFER_SELECT`
+    pub buf_index: u16,
+    pub personality: u16,
+    pub splice_fd_in: i32,
+    pub pad: [u64; 2],
+}
+
+/// Completion queue entry
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Cqe {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32,
+}
+
+impl Cqe {
+    pub fn result(&self) -> io::Result<usize> {
+        match self.res {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            res => Ok(res as usize),
+        }
+    }
+    /// Provided buffer the kernel filled
+    pub fn buffer(&self) -> Option<u16> {
+        (self.flags & CQE_F_BUFFER != 0).then_some((self.flags >> CQE_BUFFER_SHIFT) as u16)
+    }
+    /// Whether a multishot request stays armed
+    pub fn more(&self) -> bool {
+        self.flags & CQE_F_MORE != 0
+    }
+}
+
+/// Header the kernel puts before the name and payload of a multishot `recvmsg`
+#[repr(C)]
+pub struct RecvMsgOut {
+    pub namelen: u32,
+    pub controllen: u32,
+    pub payloadlen: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+struct BufReg {
+    ring_addr: u64,
+    ring_entries: u32,
+    bgid: u16,
+    flags: u16,
+    resv: [u64; 3],
+}
+
+/// Provided buffer, the tail of the ring overlays `resv` of the first one
+#[repr(C)]
+struct Buf {
+    addr: u64,
+    len: u32,
+    bid: u16,
+    resv: u16,
+}
+
+#[repr(C)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    pad: u32,
+    ts: u64,
+}
+
+/// Memory shared with the kernel, unmapped on drop
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: Option<RawFd>, offset: libc::off_t, len: usize) -> io::Result<Self> {
+        let (flags, fd) = match fd {
+            Some(fd) => (libc::MAP_SHARED | libc::MAP_POPULATE, fd),
+            None => (
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE,
+                -1,
+            ),
+        };
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, prot, flags, fd, offset) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+pub struct Ring {
+    fd: OwnedFd,
+    _rings: Mmap,
+    sqes: Mmap,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_array: *mut u32,
+    sq_mask: u32,
+    sq_entries: u32,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    /// Entries pushed since the last submission
+    queued: u32,
+}
+
+impl Ring {
+    /// Sets up a ring of `entries` submissions
+    pub fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let needed = FEAT_SINGLE_MMAP | FEAT_EXT_ARG;
+        if params.features & needed != needed {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring of this kernel is too old",
+            ));
+        }
+        let (sq, cq) = (&params.sq_off, &params.cq_off);
+        let len = (sq.array as usize + params.sq_entries as usize * size_of::<u32>())
+            .max(cq.cqes as usize + params.cq_entries as usize * size_of::<Cqe>());
+        let rings = Mmap::new(Some(fd.as_raw_fd()), OFF_SQ_RING, len)?;
+        let sqes_len = params.sq_entries as usize * size_of::<Sqe>();
+        let sqes = Mmap::new(Some(fd.as_raw_fd()), OFF_SQES, sqes_len)?;
+        Ok(Self {
+            sq_head: rings.at(sq.head),
+            sq_tail: rings.at(sq.tail),
+            sq_array: rings.at(sq.array),
+            sq_mask: unsafe { *rings.at::<u32>(sq.ring_mask) },
+            sq_entries: params.sq_entries,
+            cq_head: rings.at(cq.head),
+            cq_tail: rings.at(cq.tail),
+            cq_mask: unsafe { *rings.at::<u32>(cq.ring_mask) },
+            cqes: rings.at(cq.cqes),
+            queued: 0,
+            fd,
+            _rings: rings,
+            sqes,
+        })
+    }
+
+    /// Queues `sqe` for the next submission, false if the queue is full
+    pub fn push(&mut self, sqe: Sqe) -> bool {
+        let (head, tail) = unsafe {
+            (
+                (*self.sq_head).load(Ordering::Acquire),
+                (*self.sq_tail).load(Ordering::Relaxed),
+            )
+        };
+        if tail.wrapping_sub(head) >= self.sq_entries {
+            return false;
+        }
+        let index = tail & self.sq_mask;
+        unsafe {
+            *self.sqes.at::<Sqe>(index * size_of::<Sqe>() as u32) = sqe;
+            *self.sq_array.add(index as usize) = index;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.queued += 1;
+        true
+    }
+
+    /// Submits queued entries and waits for `wait` completions or `timeout`
+    pub fn submit_and_wait(&mut self, wait: u32, timeout: Option<Duration>) -> io::Result<()> {
+        let ts = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos().into(),
+        });
+        let arg = ts.as_ref().map(|ts| GeteventsArg {
+            sigmask: 0,
+            sigmask_sz: 0,
+            pad: 0,
+            ts: ts as *const libc::timespec as u64,
+        });
+        let mut flags = if wait > 0 { ENTER_GETEVENTS } else { 0 };
+        let (arg, arg_size) = match &arg {
+            Some(arg) => {
+                flags |= ENTER_EXT_ARG;
+                (
+                    arg as *const GeteventsArg as usize,
+                    size_of::<GeteventsArg>(),
+                )
+            }
+            None => (0, 0),
+        };
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                self.queued,
+                wait,
+                flags,
+                arg,
+                arg_size,
+            )
+        };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ETIME | libc::EINTR) => Ok(()),
+                _ => Err(err),
+            };
+        }
+        self.queued = self.queued.saturating_sub(res as u32);
+        Ok(())
+    }
+
+    /// Takes the next completion, if there is one
+    pub fn pop(&mut self) -> Option<Cqe> {
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = *self.cqes.add((head & self.cq_mask) as usize);
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(cqe)
+        }
+    }
+
+    pub fn entries(&self) -> u32 {
+        self.sq_entries
+    }
+
+    fn register(
+        &self,
+        opcode: libc::c_uint,
+        arg: *const libc::c_void,
+        count: u32,
+    ) -> io::Result<()> {
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                self.fd.as_raw_fd(),
+                opcode,
+                arg,
+                count,
+            )
+        };
+        match res {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Registers `buf` for `OP_WRITE_FIXED` with index 0
+    pub fn register_buffer(&self, buf: &mut [u8]) -> io::Result<()> {
+        let iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        self.register(REGISTER_BUFFERS, &iov as *const libc::iovec as *const _, 1)
+    }
+}
+
+/// Buffers provided to the kernel, which picks one for every receive
+pub struct BufRing {
+    ring: Mmap,
+    buffers: Box<[u8]>,
+    size: usize,
+    mask: u16,
+    tail: u16,
+}
+
+impl BufRing {
+    /// Provides `count` buffers of `size` bytes as group `group` of `ring`
+    ///
+    /// `count` has to be a power of two.
+    pub fn register(ring: &Ring, count: u16, size: usize, group: u16) -> io::Result<Self> {
+        let mut bufs = Self {
+            ring: Mmap::new(None, 0, usize::from(count) * size_of::<Buf>())?,
+            buffers: vec![0; usize::from(count) * size].into_boxed_slice(),
+            size,
+            mask: count - 1,
+            tail: 0,
+        };
+        let reg = BufReg {
+            ring_addr: bufs.ring.ptr as u64,
+            ring_entries: count.into(),
+            bgid: group,
+            flags: 0,
+            resv: [0; 3],
+        };
+        ring.register(REGISTER_PBUF_RING, &reg as *const BufReg as *const _, 1)?;
+        for bid in 0..count {
+            bufs.recycle(bid);
+        }
+        Ok(bufs)
+    }
+
+    pub fn get(&self, bid: u16) -> &[u8] {
+        let start = usize::from(bid) * self.size;
+        &self.buffers[start..start + self.size]
+    }
+
+    /// Hands buffer `bid` back to the kernel
+    pub fn recycle(&mut self, bid: u16) {
+        let start = usize::from(bid) * self.size;
+        let buf = Buf {
+            addr: self.buffers[start..].as_ptr() as u64,
+            len: self.size as u32,
+            bid,
+            resv: 0,
+        };
+        let index = u32::from(self.tail & self.mask);
+        unsafe {
+            let entry = self.ring.at::<Buf>(index * size_of::<Buf>() as u32);
+            // keeps the tail, which lives in `resv` of the first entry
+            ptr::addr_of_mut!((*entry).addr).write(buf.addr);
+            ptr::addr_of_mut!((*entry).len).write(buf.len);
+            ptr::addr_of_mut!((*entry).bid).write(buf.bid);
+        }
+        self.tail = self.tail.wrapping_add(1);
+        let tail = self.ring.at::<AtomicU16>(14);
+        unsafe { (*tail).store(self.tail, Ordering::Release) };
+    }
+}
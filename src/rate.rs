@@ -0,0 +1,169 @@
+/// Module for transmission rates and their units
+use std::{fmt, str::FromStr};
+
+/// Bits per second
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Bitrate(pub u64);
+
+impl Bitrate {
+    pub fn from_bytes_per_second(bytes: f64) -> Self {
+        Self((bytes * 8.).round() as u64)
+    }
+    pub fn bits_per_second(&self) -> u64 {
+        self.0
+    }
+    pub fn bytes_per_second(&self) -> f64 {
+        self.0 as f64 / 8.
+    }
+}
+
+/// Parses a number with an optional decimal (`k`, `M`, `G`, `T`) or binary
+/// (`Ki`, `Mi`, `Gi`, `Ti`) prefix and an optional unit.
+///
+/// Bits are assumed unless the unit says otherwise: `bit`, `bits`, `b`,
+/// `bps`, `bit/s` for bits and `B`, `Bps`, `B/s`, `byte`, `bytes` for bytes.
+impl FromStr for Bitrate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (num, suffix) = s.split_at(split);
+        let num: f64 = num
+            .parse()
+            .map_err(|e| format!("invalid rate `{s}`: {e}"))?;
+        let suffix = suffix.trim_start();
+
+        let mut chars = suffix.chars();
+        let prefix = match chars.next().map(|c| c.to_ascii_lowercase()) {
+            Some('k') => 1,
+            Some('m') => 2,
+            Some('g') => 3,
+            Some('t') => 4,
+            _ => 0,
+        };
+        let (multiplier, unit) = if prefix == 0 {
+            (1., suffix)
+        } else if let Some(unit) = chars.as_str().strip_prefix('i') {
+            (1024_f64.powi(prefix), unit)
+        } else {
+            (1000_f64.powi(prefix), chars.as_str())
+        };
+        let bits_in_unit = match unit {
+            "" | "b" | "bit" | "bits" | "bps" | "bit/s" => 1.,
+            "B" | "Bps" | "B/s" | "byte" | "bytes" => 8.,
+            _ => return Err(format!("invalid rate `{s}`: unknown unit `{suffix}`")),
+        };
+        Ok(Self((num * multiplier * bits_in_unit).round() as u64))
+    }
+}
+
+/// Formats with the largest decimal prefix, e.g. `2.50 Gbit/s`
+impl fmt::Display for Bitrate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const PREFIXES: [&str; 5] = ["", "k", "M", "G", "T"];
+        let mut value = self.0 as f64;
+        let mut prefix = 0;
+        while value >= 1000. && prefix < PREFIXES.len() - 1 {
+            value /= 1000.;
+            prefix += 1;
+        }
+        f.pad(&format!("{value:.2} {}bit/s", PREFIXES[prefix]))
+    }
+}
+
+/// Target rate of frames of a known size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    frames_per_second: f64,
+    frame_size: u16,
+}
+
+impl Rate {
+    /// Rate which sends `frame_size` frames to saturate `bitrate`
+    pub fn bandwidth(bitrate: Bitrate, frame_size: u16) -> Self {
+        Self {
+            frames_per_second: bitrate.bytes_per_second() / f64::from(frame_size),
+            frame_size,
+        }
+    }
+    /// Rate of `pps` frames per second regardless of their size
+    pub fn packets(pps: u64, frame_size: u16) -> Self {
+        Self {
+            frames_per_second: pps as f64,
+            frame_size,
+        }
+    }
+    /// Rate without any limit
+    pub fn unlimited(frame_size: u16) -> Self {
+        Self::packets(0, frame_size)
+    }
+    pub fn is_unlimited(&self) -> bool {
+        self.frames_per_second == 0.
+    }
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames_per_second
+    }
+    pub fn bytes_per_second(&self) -> f64 {
+        self.frames_per_second * f64::from(self.frame_size)
+    }
+    pub fn bitrate(&self) -> Bitrate {
+        Bitrate::from_bytes_per_second(self.bytes_per_second())
+    }
+    pub fn frame_size(&self) -> u16 {
+        self.frame_size
+    }
+    /// Same frame rate, but with frames of `frame_size`
+    pub fn with_frame_size(self, frame_size: u16) -> Self {
+        Self { frame_size, ..self }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_unlimited() {
+            return f.pad("unlimited");
+        }
+        f.pad(&format!(
+            "{} ({:.0} pps)",
+            self.bitrate(),
+            self.frames_per_second
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bitrate() {
+        let parse = |s: &str| s.parse::<Bitrate>().map(|b| b.0);
+        assert_eq!(parse("1000"), Ok(1000));
+        assert_eq!(parse("500k"), Ok(500_000));
+        assert_eq!(parse("100M"), Ok(100_000_000));
+        assert_eq!(parse("2.5G"), Ok(2_500_000_000));
+        assert_eq!(parse("10Mi"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse("100Mbit"), Ok(100_000_000));
+        assert_eq!(parse("10MB"), Ok(80_000_000));
+        assert_eq!(parse("1KiB/s"), Ok(8192));
+        assert!(parse("10Q").is_err());
+        assert!(parse("M").is_err());
+    }
+
+    #[test]
+    fn test_display_bitrate() {
+        assert_eq!(Bitrate(999).to_string(), "999.00 bit/s");
+        assert_eq!(Bitrate(2_500_000_000).to_string(), "2.50 Gbit/s");
+        assert_eq!(format!("{:>14}", Bitrate(64_000)), "  64.00 kbit/s");
+    }
+
+    #[test]
+    fn test_rate() {
+        let rate = Rate::bandwidth("12M".parse().unwrap(), 1500);
+        assert_eq!(rate.frames_per_second(), 1000.);
+        assert_eq!(Rate::packets(1000, 1500), rate);
+        assert!(Rate::bandwidth(Bitrate(0), 1500).is_unlimited());
+    }
+}
@@ -0,0 +1,259 @@
+/// Module for the receiver's reports and their publication without locks
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use crate::{
+    frames::{FrameHandler, FrameStatistics},
+    rate::Bitrate,
+};
+
+/// Header of the report table
+pub const HEADER: &str =
+    "[ ID]    Latency        Bitrate      Sess.Avg. |Bad, Mangled|  Lost/Total";
+
+/// Header of the table for streams, which lose no frames but skip records to resync
+pub const STREAM_HEADER: &str =
+    "[ ID]    Latency        Bitrate      Sess.Avg. |Bad, Mangled| Skipped/Total";
+
+/// Receiver's view of a session at some moment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub statistics: FrameStatistics,
+    /// Average bitrate of the session
+    pub avg: Bitrate,
+    /// Bitrate of the last measurement interval
+    pub instant: Bitrate,
+    /// Mean gap between frames of the current burst, us
+    pub latency: u64,
+}
+
+impl Report {
+    /// Report of `handler`, `None` before its first frame
+    pub fn of(handler: &FrameHandler) -> Option<Self> {
+        let statistics = handler.get_statistics()?.clone();
+        let (avg, instant) = handler.get_speeds();
+        Some(Self {
+            statistics,
+            avg,
+            instant,
+            latency: handler.get_latency() as u64,
+        })
+    }
+
+    /// Sum of reports of concurrent sessions, with the mean latency
+    pub fn sum(reports: &[Report]) -> Option<Self> {
+        let (first, rest) = reports.split_first()?;
+        let mut sum = first.clone();
+        for report in rest {
+            let (total, stats) = (&mut sum.statistics, &report.statistics);
+            total.valid += stats.valid;
+            total.invalid += stats.invalid;
+            total.out_of_order += stats.out_of_order;
+            total.internally_bad += stats.internally_bad;
+            total.lost += stats.lost;
+            total.resyncs += stats.resyncs;
+            total.dropped += stats.dropped;
+            sum.avg.0 += report.avg.0;
+            sum.instant.0 += report.instant.0;
+            sum.latency += report.latency;
+        }
+        sum.latency /= reports.len() as u64;
+        Some(sum)
+    }
+
+    /// Row of the report table labelled `id`
+    pub fn line(&self, id: &str) -> String {
+        let Self {
+            statistics: stats,
+            avg,
+            instant,
+            latency,
+        } = self;
+        let total = stats.total();
+        let percent = stats.loss_percent();
+        let dropped = match stats.dropped {
+            0 => String::new(),
+            n => format!(
+                " [{n} dropped by host, {} on network]",
+                stats.network_lost()
+            ),
+        };
+        let resyncs = match stats.resyncs {
+            0 => String::new(),
+            n => format!(" [{n} resyncs]"),
+        };
+        format!(
+            "[{id: >3}] {latency: >8}us {instant: >14} {avg: >14} |{}, {}| {pad: >5}{}/{total} ({percent:.2}%){dropped}{resyncs}",
+            stats.invalid,
+            stats.internally_bad,
+            stats.lost,
+            pad = ""
+        )
+    }
+}
+
+/// Words of a report, the first one tells if there is a report at all
+const FIELDS: usize = 12;
+
+/// Latest report of a single writer, which readers take without stalling it
+///
+/// A sequence lock: the writer keeps the sequence odd while it updates the
+/// fields, readers retry until they see the same even sequence around them.
+#[derive(Default)]
+pub struct ReportCell {
+    seq: AtomicU64,
+    fields: [AtomicU64; FIELDS],
+}
+
+impl ReportCell {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the report, `None` clears it
+    ///
+    /// Must not be called from more than one thread at once.
+    pub fn publish(&self, report: Option<&Report>) {
+        let words = match report {
+            Some(Report {
+                statistics: stats,
+                avg,
+                instant,
+                latency,
+            }) => [
+                1,
+                stats.session_id,
+                stats.valid,
+                stats.invalid,
+                stats.out_of_order,
+                stats.internally_bad,
+                stats.lost,
+                stats.resyncs,
+                stats.dropped,
+                avg.0,
+                instant.0,
+                *latency,
+            ],
+            None => [0; FIELDS],
+        };
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (field, word) in self.fields.iter().zip(words) {
+            field.store(word, Ordering::Relaxed);
+        }
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// The latest report, if there is one
+    pub fn read(&self) -> Option<Report> {
+        let words = loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let words = self
+                .fields
+                .each_ref()
+                .map(|field| field.load(Ordering::Relaxed));
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                break words;
+            }
+        };
+        let [present, session_id, valid, invalid, out_of_order, internally_bad, lost, resyncs, dropped, avg, instant, latency] =
+            words;
+        (present == 1).then_some(Report {
+            statistics: FrameStatistics {
+                session_id,
+                valid,
+                invalid,
+                out_of_order,
+                internally_bad,
+                lost,
+                resyncs,
+                dropped,
+            },
+            avg: Bitrate(avg),
+            instant: Bitrate(instant),
+            latency,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    fn report(n: u64) -> Report {
+        let mut statistics = FrameStatistics::new(n);
+        statistics.valid = n;
+        statistics.lost = n;
+        Report {
+            statistics,
+            avg: Bitrate(n),
+            instant: Bitrate(n),
+            latency: n,
+        }
+    }
+
+    #[test]
+    fn test_cell_reads_whole_reports() {
+        let cell = Arc::new(ReportCell::new());
+        assert_eq!(cell.read(), None);
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (cell, done) = (cell.clone(), done.clone());
+            std::thread::spawn(move || {
+                for n in 1..200_000 {
+                    cell.publish(Some(&report(n)));
+                }
+                done.store(true, Ordering::Relaxed);
+            })
+        };
+        while !done.load(Ordering::Relaxed) {
+            if let Some(read) = cell.read() {
+                // never a mix of two reports
+                assert_eq!(read, report(read.latency));
+            }
+        }
+        writer.join().unwrap();
+        cell.publish(None);
+        assert_eq!(cell.read(), None);
+    }
+
+    #[test]
+    fn test_sum() {
+        let sum = Report::sum(&[report(1), report(3)]).unwrap();
+        assert_eq!(
+            (sum.statistics.valid, sum.avg, sum.latency),
+            (4, Bitrate(4), 2)
+        );
+        assert_eq!(Report::sum(&[]), None);
+    }
+
+    #[test]
+    fn test_network_loss() {
+        let mut report = report(10);
+        report.statistics.dropped = 4;
+        assert!(report
+            .line("1")
+            .ends_with("[4 dropped by host, 6 on network]"));
+        // the socket's drops may include datagrams of other senders
+        report.statistics.dropped = 20;
+        assert!(report
+            .line("1")
+            .ends_with("[20 dropped by host, 0 on network]"));
+    }
+
+    #[test]
+    fn test_loss_percent() {
+        let mut report = report(10);
+        report.statistics.invalid = 5;
+        // the row tells the same loss as the summary
+        assert!(report.line("1").ends_with("10/25 (40.00%)"));
+        let summary = report.statistics.to_string();
+        assert!(summary.contains("10/25 lost (40.00% loss)"));
+    }
+}
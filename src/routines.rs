@@ -1,5 +1,11 @@
 use std::{
-    sync::{Arc, RwLock},
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -7,70 +13,162 @@ use anyhow::Result;
 use spin_sleep::sleep;
 type ShutdownReceiver = std::sync::mpsc::Receiver<()>;
 
+/// How long the sender waits for the final feedback after the stream is over
+const FINISH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Interval between repeated end of stream notifications
+const FINISH_RETRY: Duration = Duration::from_millis(200);
+
+/// Interval between checks for receiver's feedback while sending
+const FEEDBACK_POLL: Duration = Duration::from_millis(10);
+
+/// Attempts to send a frame failing with transient errors before it's dropped
+const SEND_RETRIES: u32 = 100;
+
+/// Pause between attempts to send a frame
+const RETRY_BACKOFF: Duration = Duration::from_micros(100);
+
+/// Flows of a receiving worker reported at once, more are reported as others end
+const WORKER_FLOWS: usize = 64;
+
+/// Flows of a receiving worker silent for that long are closed and free their report cells,
+/// a sender paused for longer comes back as a new flow
+const FLOW_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Final results a receiving worker keeps for senders which missed them
+const WORKER_FINALS: usize = 64;
+
+/// Interval between publications of the receiving loops' reports
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(10);
+
 use crate::{
-    frames::{FrameBuilder, FrameHandler},
-    protocols::{ProtoError, Receiver, Sender},
+    accuracy::{SendAccuracy, SendError},
+    control::{Command, Control},
+    feedback::Feedback,
+    frames::{FrameBatch, FrameBuilder, FrameHandler},
+    framing::Framing,
+    protocols::{ProtoError, Receiver, Sender, UdpReceiver},
+    rate::Rate,
+    report::{Report, ReportCell, HEADER, STREAM_HEADER},
+    snmp::HostErrors,
     speed_controllers::Limiter,
 };
 
 /// Serial loop for receiving data on a Receiver implementer.
 ///
 /// Reports stats every `report_interval`` second unless blocked.
+/// Sends stats back to the peer every `feedback_interval` if set.
+/// The loop owns the handler and publishes its stats for the report thread
+/// without locks every `PUBLISH_INTERVAL`. Frames the socket dropped count
+/// towards the session's losses, the `host` errors are reported along with them.
+/// Over TCP, which retransmits lost frames, the loop prints the connection's
+/// `TCP_INFO` every interval and at the end of the session.
 ///
 pub fn reciever_loop(
     mut socket: impl Receiver,
+    framing: Box<dyn Framing>,
     report_interval: u8,
+    feedback_interval: Option<Duration>,
+    mut host: Option<HostErrors>,
     shutdown: ShutdownReceiver,
 ) -> Result<()> {
-    let handler = Arc::new(RwLock::new(FrameHandler::new()));
+    let mut handler = FrameHandler::new(framing);
+    let cell = Arc::new(ReportCell::new());
+    let header = if socket.is_stream() {
+        STREAM_HEADER
+    } else {
+        HEADER
+    };
     let print_killer = {
-        let handler = handler.clone();
+        let cell = cell.clone();
         let (tx, rx) = std::sync::mpsc::channel::<()>();
         let mut need_to_print_header = true;
-        std::thread::spawn(move || {
-            loop {
-                if rx.try_recv().is_ok() {
-                    return ();
-                }
-                {
-                    let guard = handler.read().unwrap();
-                    if let Some(stats) = guard.get_statistics() {
-                        if need_to_print_header {
-                            need_to_print_header = false;
-                            println!("[ ID]    Latency      Bitrate   Sess.Avg. |Bad, Mangled|  Lost/Total")
-                        }
-                        let (avg, instant) = guard.get_speeds();
-                        let total = stats.valid + stats.invalid + stats.lost + stats.internally_bad;
-                        let invalid = stats.invalid;
-                        let internally_bad = stats.internally_bad;
-                        let lost = stats.lost;
-                        let percent = lost as f64 / total as f64 * 100_f64;
-                        println!("[{: >3}] {: >8}us {instant: >8}kbps {avg: >8}kbps {pad: >3}|{invalid}, {internally_bad}| {pad: >5}{lost}/{total} ({percent:.2}%)", stats.session_id, guard.get_latency(), pad="");
-                    } else {
-                        need_to_print_header = true;
-                    }
+        std::thread::spawn(move || loop {
+            if rx.try_recv().is_ok() {
+                return;
+            }
+            if let Some(report) = cell.read() {
+                if need_to_print_header {
+                    need_to_print_header = false;
+                    println!("{header}")
                 }
-                std::thread::sleep(Duration::from_secs(report_interval.into()));
+                println!("{}", report.line(&report.statistics.session_id.to_string()));
+            } else {
+                need_to_print_header = true;
+            }
+            if let Some(errors) = host.as_mut().and_then(HostErrors::poll) {
+                println!("[HOST] {errors}");
             }
+            std::thread::sleep(Duration::from_secs(report_interval.into()));
         });
         tx
     };
+    let mut peer = None;
+    // results of the last session, sent again if its end is repeated
+    let mut last_final: Option<(SocketAddr, Feedback)> = None;
+    let mut last_feedback = Instant::now();
+    let mut last_publish = Instant::now();
+    let mut last_tcp_info = Instant::now();
+    let mut dropped = socket.dropped();
     loop {
         if shutdown.try_recv().is_ok() {
             let _ = print_killer.send(());
             return Ok(());
         }
-        match socket.recv() {
-            Ok(data) => {
-                handler.write().unwrap().handle(data);
-            },
-            Err(ProtoError::Connected(peer)) => {
-                eprintln!("Peer connected: {peer}");
-            },
-            Err(ProtoError::Disconnected(peer)) => {
-                eprintln!("Peer disconnected: {peer}");
-                handler.write().unwrap().reset();
-            },
+        if let (Some(peer), Some(interval)) = (peer, feedback_interval) {
+            if last_feedback.elapsed() >= interval {
+                last_feedback = Instant::now();
+                if let Some(stats) = handler.get_statistics() {
+                    let _ = socket.reply(peer, &Feedback::new(false, stats.clone()).to_bytes());
+                }
+            }
+        }
+        if last_publish.elapsed() >= PUBLISH_INTERVAL {
+            last_publish = Instant::now();
+            cell.publish(Report::of(&handler).as_ref());
+        }
+        let tcp_interval = Duration::from_secs(report_interval.into());
+        if peer.is_some() && last_tcp_info.elapsed() >= tcp_interval {
+            last_tcp_info = Instant::now();
+            if let Some(info) = socket.tcp_info() {
+                println!("[TCP] {info}");
+            }
+        }
+        let received = socket.recv_batch(&mut |data, received| handler.handle_at(data, received));
+        // drops since the last batch were frames of the current session
+        let now_dropped = socket.dropped();
+        if let (Some(before), Some(now)) = (dropped, now_dropped) {
+            handler.dropped(now - before);
+        }
+        dropped = now_dropped;
+        match received {
+            Ok(_) => {}
+            Err(ProtoError::Connected(addr)) => {
+                eprintln!("Peer connected: {addr}");
+                peer = Some(addr);
+                last_tcp_info = Instant::now();
+            }
+            Err(ProtoError::Disconnected(addr)) => {
+                eprintln!("Peer disconnected: {addr}");
+                peer = None;
+                if let Some(info) = socket.tcp_info() {
+                    println!("TCP summary: {info}");
+                }
+                if let Some(stats) = handler.get_statistics() {
+                    let feedback = Feedback::new(true, stats.clone());
+                    if let Err(e) = socket.reply(addr, &feedback.to_bytes()) {
+                        eprintln!("Failed to send results to {addr}: {e}");
+                    }
+                    last_final = Some((addr, feedback));
+                }
+                handler.reset();
+                cell.publish(None);
+            }
+            Err(ProtoError::Ended(addr)) => {
+                if let Some((_, feedback)) = last_final.as_ref().filter(|(peer, _)| *peer == addr) {
+                    let _ = socket.reply(addr, &feedback.to_bytes());
+                }
+            }
             Err(ProtoError::IOErr(_err)) => {
                 // TODO: maybe nonblock, thonk
                 // if err.kind() == std::io::ErrorKind::WouldBlock {
@@ -78,38 +176,539 @@ pub fn reciever_loop(
                 // } else {
 
                 // }
-            },
+            }
             Err(ProtoError::ConflictingClient(peer)) => {
                 eprintln!("Datagram from a different peer ignored: {peer}");
             }
+            Err(ProtoError::Desynchronized(peer, skipped)) => {
+                eprintln!("Stream from {peer} desynchronized, skipped {skipped} bytes");
+                handler.resynced();
+            }
         }
     }
 }
 
-/// Serial loop for sending data over Sender implementer.
+/// Session of a peer served by a receiving worker
+struct Flow {
+    handler: FrameHandler,
+    /// Worker's report cell, none while all of them are taken
+    cell: Option<usize>,
+    /// End of stream has arrived, the flow is closed after the batch
+    ended: bool,
+    /// Frames have arrived since the last check for idle flows
+    active: bool,
+}
+
+/// Parallel loop of `workers` threads receiving on sockets sharing a port
 ///
-/// Takes `impl Limiter` for speed adjustment on the fly.
+/// Every worker binds a socket with `bind`, serves the flows the kernel hands
+/// to it, each with a handler of its own, and publishes their statistics
+/// without locks, so reporting them every `report_interval` seconds never
+/// stalls receiving. Workers are pinned to `cpus` round robin, if there are any.
+/// A socket's drops can't be told apart by flow, the `host` errors cover them.
 ///
+#[allow(clippy::too_many_arguments)]
+pub fn sharded_receiver_loop(
+    workers: usize,
+    bind: impl Fn() -> Result<UdpReceiver> + Sync,
+    cpus: &[u16],
+    framing: impl Fn() -> Box<dyn Framing> + Sync,
+    report_interval: u8,
+    feedback_interval: Option<Duration>,
+    mut host: Option<HostErrors>,
+    shutdown: ShutdownReceiver,
+) -> Result<()> {
+    let cells: Vec<Vec<ReportCell>> = (0..workers)
+        .map(|_| (0..WORKER_FLOWS).map(|_| ReportCell::new()).collect())
+        .collect();
+    let sessions = AtomicU64::new(1);
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        // closed once every worker is bound
+        let (failed_tx, failed) = std::sync::mpsc::channel::<anyhow::Error>();
+        for (i, cells) in cells.iter().enumerate() {
+            let cpu = (!cpus.is_empty()).then(|| cpus[i % cpus.len()]);
+            let (bind, framing, sessions, stop) = (&bind, &framing, &sessions, &stop);
+            let failed_tx = failed_tx.clone();
+            scope.spawn(move || {
+                if let Some(cpu) = cpu {
+                    if let Err(e) = pin_to_cpu(cpu) {
+                        eprintln!("Worker {i} isn't pinned to CPU {cpu}: {e}");
+                    }
+                }
+                // bound in its thread, so the socket's memory is local to the pinned CPU
+                let socket = match bind() {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        let _ = failed_tx.send(e);
+                        return;
+                    }
+                };
+                drop(failed_tx);
+                receiver_worker(socket, cells, framing, sessions, feedback_interval, stop)
+            });
+        }
+        drop(failed_tx);
+        if let Ok(e) = failed.recv() {
+            stop.store(true, Ordering::Relaxed);
+            return Err(e);
+        }
+        let interval = Duration::from_secs(report_interval.into());
+        let mut need_to_print_header = true;
+        loop {
+            match shutdown.recv_timeout(interval) {
+                Ok(()) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => std::thread::sleep(interval),
+            }
+            if let Some(errors) = host.as_mut().and_then(HostErrors::poll) {
+                println!("[HOST] {errors}");
+            }
+            let mut reports: Vec<Report> = cells
+                .iter()
+                .flatten()
+                .filter_map(ReportCell::read)
+                .collect();
+            if reports.is_empty() {
+                need_to_print_header = true;
+                continue;
+            }
+            if need_to_print_header {
+                need_to_print_header = false;
+                println!("{HEADER}")
+            }
+            reports.sort_by_key(|report| report.statistics.session_id);
+            for report in &reports {
+                println!("{}", report.line(&report.statistics.session_id.to_string()));
+            }
+            if let (true, Some(sum)) = (reports.len() > 1, Report::sum(&reports)) {
+                println!("{}", sum.line("SUM"));
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        Ok(())
+    })
+}
+
+/// Serves the flows arriving at `socket` until `stop`, publishing their reports to `cells`
+fn receiver_worker(
+    mut socket: UdpReceiver,
+    cells: &[ReportCell],
+    framing: &(dyn Fn() -> Box<dyn Framing> + Sync),
+    sessions: &AtomicU64,
+    feedback_interval: Option<Duration>,
+    stop: &AtomicBool,
+) {
+    let mut flows: HashMap<SocketAddr, Flow> = HashMap::new();
+    let mut free: Vec<usize> = (0..cells.len()).rev().collect();
+    let mut ended = Vec::new();
+    // results of closed flows, for senders repeating the end of their stream
+    let mut finals: VecDeque<(SocketAddr, Feedback)> = VecDeque::new();
+    let mut repeated = Vec::new();
+    let mut last_publish = Instant::now();
+    let mut last_feedback = Instant::now();
+    let mut last_idle_check = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        // timeouts only let the worker check for `stop`
+        let _ = socket.recv_from_batch(&mut |addr, frame, received| {
+            if frame == [0] {
+                match flows.get_mut(&addr) {
+                    Some(flow) if !flow.ended => {
+                        eprintln!("Peer disconnected: {addr}");
+                        flow.ended = true;
+                        ended.push(addr);
+                    }
+                    Some(_) => {}
+                    None => repeated.push(addr),
+                }
+                return;
+            }
+            let flow = flows.entry(addr).or_insert_with(|| {
+                eprintln!("Peer connected: {addr}");
+                let session_id = sessions.fetch_add(1, Ordering::Relaxed);
+                Flow {
+                    handler: FrameHandler::with_session_id(framing(), session_id),
+                    cell: free.pop(),
+                    ended: false,
+                    active: true,
+                }
+            });
+            if !flow.ended {
+                flow.handler.handle_at(frame, received);
+                flow.active = true;
+            }
+        });
+        if last_idle_check.elapsed() >= FLOW_TIMEOUT {
+            last_idle_check = Instant::now();
+            for (addr, flow) in &mut flows {
+                if !flow.active && !flow.ended {
+                    eprintln!("Peer timed out: {addr}");
+                    flow.ended = true;
+                    ended.push(*addr);
+                }
+                flow.active = false;
+            }
+        }
+        for addr in ended.drain(..) {
+            let Some(flow) = flows.remove(&addr) else {
+                continue;
+            };
+            if let Some(stats) = flow.handler.get_statistics() {
+                let feedback = Feedback::new(true, stats.clone());
+                if let Err(e) = socket.reply(addr, &feedback.to_bytes()) {
+                    eprintln!("Failed to send results to {addr}: {e}");
+                }
+                if finals.len() == WORKER_FINALS {
+                    finals.pop_front();
+                }
+                finals.push_back((addr, feedback));
+            }
+            if let Some(cell) = flow.cell {
+                cells[cell].publish(None);
+                free.push(cell);
+            }
+        }
+        for addr in repeated.drain(..) {
+            if let Some((_, feedback)) = finals.iter().rev().find(|(peer, _)| *peer == addr) {
+                let _ = socket.reply(addr, &feedback.to_bytes());
+            }
+        }
+        if last_publish.elapsed() >= PUBLISH_INTERVAL {
+            last_publish = Instant::now();
+            for flow in flows.values_mut() {
+                if flow.cell.is_none() {
+                    flow.cell = free.pop();
+                }
+                if let Some(cell) = flow.cell {
+                    cells[cell].publish(Report::of(&flow.handler).as_ref());
+                }
+            }
+        }
+        if let Some(interval) = feedback_interval {
+            if last_feedback.elapsed() >= interval {
+                last_feedback = Instant::now();
+                for (addr, flow) in &flows {
+                    if let Some(stats) = flow.handler.get_statistics() {
+                        let _ =
+                            socket.reply(*addr, &Feedback::new(false, stats.clone()).to_bytes());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Binds the calling thread to `cpu`
+#[cfg(target_os = "linux")]
+fn pin_to_cpu(cpu: u16) -> std::io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // below CPU_SETSIZE, as the arguments allow
+    unsafe { libc::CPU_SET(cpu.into(), &mut set) };
+    match unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_cpu(_cpu: u16) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "CPU affinity is only set on Linux",
+    ))
+}
+
+/// Outcome of a sending session
+pub struct SenderResult {
+    /// Session was stopped by the user
+    pub interrupted: bool,
+    /// Frames handed to the socket
+    pub sent: u64,
+    /// Receiver's final statistics, if they have arrived
+    pub feedback: Option<Feedback>,
+}
+
+/// Passes `rate` on to the kernel if it paces `socket`
+fn repace(
+    socket: &mut impl Sender,
+    limiter: &impl Limiter,
+    control: Option<&Control>,
+    rate: &Rate,
+) {
+    let Some(control) = control.filter(|_| limiter.kernel_paced()) else {
+        return;
+    };
+    if let Err(e) = control.pacing.apply(socket, rate) {
+        eprintln!("Kernel pacing can't follow the new rate: {e}");
+    }
+}
+
+/// Serial loop for sending data over Sender implementer.
+///
+/// Takes `impl Limiter` for speed adjustment on the fly. Runs until shutdown,
+/// send error or for `duration` if set, then collects receiver's results.
+/// Rate, MTU and pausing can be changed with `control` commands meanwhile.
+/// Frames the limiter lets go without a pause are sent in batches, if the socket takes them.
 pub fn sender_loop(
     mut socket: impl Sender,
     mtu: u16,
+    framing: Box<dyn Framing>,
     mut limiter: impl Limiter,
-    shutdown: ShutdownReceiver,
-) -> Result<()> {
-    let mut builder = FrameBuilder::new(mtu);
-    let mut time = Instant::now();
+    duration: Option<Duration>,
+    shutdown: &ShutdownReceiver,
+    control: Option<Control>,
+) -> Result<SenderResult> {
+    let mut builder = FrameBuilder::new(mtu, framing);
+    let mut frame_size = mtu;
+    let start = Instant::now();
+    let mut time = start;
+    let mut last_poll = start;
     let report_interval = Duration::from_secs(1);
-    loop {
+    let mut sent = 0_u64;
+    let mut paused = false;
+    let mut last_feedback = None;
+    let mut accuracy = SendAccuracy::new();
+    let mut batch = FrameBatch::new(socket.batch_size());
+    let interrupted = loop {
         if shutdown.try_recv().is_ok() {
-            return Ok(());
+            break true;
+        }
+        if duration.is_some_and(|duration| start.elapsed() >= duration) {
+            break false;
         }
         if time.elapsed() > report_interval {
-            println!("Avg send speed: {} kbps", builder.get_avg_kbps());
+            println!(
+                "{}",
+                accuracy.report(builder.get_avg_rate(), limiter.target())
+            );
+            if let Some(info) = socket.tcp_info() {
+                println!("[TCP] {info}");
+            }
             time = Instant::now();
         }
-        if let Err(_) = &socket.send(builder.next()) {
-            return Ok(());
+        if last_poll.elapsed() >= FEEDBACK_POLL {
+            last_poll = Instant::now();
+            while let Ok(Some(reply)) = socket.recv_reply(Duration::ZERO) {
+                if let Some(feedback) = Feedback::from_bytes(reply) {
+                    limiter.feedback(&feedback);
+                    last_feedback = Some(feedback);
+                }
+            }
+            let commands = control
+                .iter()
+                .flat_map(|control| control.commands.try_iter());
+            for command in commands {
+                match command {
+                    Command::Rate(bitrate) => {
+                        let mut rate = Rate::bandwidth(bitrate, frame_size);
+                        let unlimited = control.as_ref().and_then(|control| control.unlimited);
+                        if let Some(bitrate) = unlimited.filter(|_| rate.is_unlimited()) {
+                            rate = Rate::bandwidth(bitrate, frame_size);
+                        }
+                        if limiter.set_rate(rate) {
+                            repace(&mut socket, &limiter, control.as_ref(), &rate);
+                            println!("Target rate: {rate}");
+                        } else {
+                            eprintln!("Limiter doesn't follow a set rate");
+                        }
+                    }
+                    Command::Pause => {
+                        paused = true;
+                        limiter.pause(true);
+                        println!("Paused");
+                    }
+                    Command::Resume => {
+                        paused = false;
+                        limiter.pause(false);
+                        println!("Resumed");
+                    }
+                    Command::Mtu(mtu) => {
+                        let headers = control.as_ref().and_then(|control| control.headers);
+                        let Some(headers) = headers else {
+                            eprintln!("Frame size can't change with fixed framing");
+                            continue;
+                        };
+                        match mtu.checked_sub(headers) {
+                            Some(size) if builder.resize(size) => {
+                                frame_size = size;
+                                // same bitrate with the new frames
+                                if let Some(target) = limiter.target() {
+                                    let rate = Rate::bandwidth(target, size);
+                                    if limiter.set_rate(rate) {
+                                        repace(&mut socket, &limiter, control.as_ref(), &rate);
+                                    }
+                                }
+                                println!("MTU {mtu}, frames of {size} bytes");
+                            }
+                            _ => eprintln!("MTU {mtu} is too small for the protocol and framing"),
+                        }
+                    }
+                    Command::Stats => {
+                        let target = match limiter.target() {
+                            Some(target) => format!(", target: {target}"),
+                            None => String::new(),
+                        };
+                        let state = if paused { ", paused" } else { "" };
+                        println!(
+                            "Sent {sent} frames of {frame_size} bytes, avg send speed: {}{target}{state}",
+                            builder.get_avg_rate()
+                        );
+                        if let Some(feedback) = &last_feedback {
+                            println!("Server: {}", feedback.statistics);
+                        }
+                    }
+                }
+            }
+        }
+        if paused {
+            if let Err(e) = flush(
+                &mut socket,
+                &mut batch,
+                &mut builder,
+                &mut sent,
+                &mut accuracy,
+            ) {
+                eprintln!("Sending failed: {e}");
+                break false;
+            }
+            sleep(FEEDBACK_POLL);
+            accuracy.slept(FEEDBACK_POLL);
+            continue;
+        }
+        if limiter.is_done() {
+            break false;
+        }
+        let frame = match limiter.frame_size() {
+            Some(size) => builder.next_sized(size.into()),
+            None => builder.next(),
         };
-        sleep(limiter.sleep_interval());
+        batch.push(frame);
+        let interval = limiter.sleep_interval();
+        if interval.is_zero() && !batch.is_full() {
+            continue;
+        }
+        if let Err(e) = flush(
+            &mut socket,
+            &mut batch,
+            &mut builder,
+            &mut sent,
+            &mut accuracy,
+        ) {
+            eprintln!("Sending failed: {e}");
+            break false;
+        }
+        let slept = Instant::now();
+        sleep(interval);
+        accuracy.slept(slept.elapsed());
+    };
+    if let Err(e) = flush(
+        &mut socket,
+        &mut batch,
+        &mut builder,
+        &mut sent,
+        &mut accuracy,
+    ) {
+        eprintln!("Sending failed: {e}");
+    }
+    if let Some(summary) = limiter.summary() {
+        println!("{summary}");
+    }
+    let errors = accuracy.total_errors();
+    if !errors.is_empty() {
+        println!("Send errors: {errors}");
+    }
+    let feedback = finish_session(&mut socket);
+    if let Some(info) = socket.tcp_info() {
+        println!("TCP summary: {info}");
+    }
+    Ok(SenderResult {
+        interrupted,
+        sent,
+        feedback,
+    })
+}
+
+/// Sends and empties `batch`, retrying on transient errors
+///
+/// A frame failing `SEND_RETRIES` times in a row is given up on along with
+/// the rest of the batch, and `builder` reuses their counters. Transmit stamps
+/// of the batches so far are collected as well.
+fn flush(
+    socket: &mut impl Sender,
+    batch: &mut FrameBatch,
+    builder: &mut FrameBuilder,
+    sent: &mut u64,
+    accuracy: &mut SendAccuracy,
+) -> Result<(), ProtoError> {
+    batch.with_frames(|frames| send_frames(socket, frames, builder, sent, accuracy))?;
+    batch.clear();
+    // drained as it fills, the error queue takes from the receive buffer replies need
+    socket.tx_delays(&mut |delay| accuracy.transmitted(delay));
+    Ok(())
+}
+
+/// Sends `frames` for `flush`
+fn send_frames(
+    socket: &mut impl Sender,
+    frames: &[&[u8]],
+    builder: &mut FrameBuilder,
+    sent: &mut u64,
+    accuracy: &mut SendAccuracy,
+) -> Result<(), ProtoError> {
+    let mut next = 0;
+    let mut failures = 0;
+    while next < frames.len() {
+        let start = Instant::now();
+        match socket.send_batch(&frames[next..]) {
+            Ok(count) => {
+                let bytes = frames[next..next + count]
+                    .iter()
+                    .map(|frame| frame.len())
+                    .sum();
+                accuracy.sent(count, bytes, start.elapsed());
+                *sent += count as u64;
+                next += count;
+                failures = 0;
+                continue;
+            }
+            Err(e) => {
+                let kind = SendError::of(&e);
+                accuracy.failed(kind);
+                if !kind.is_transient() {
+                    return Err(e);
+                }
+            }
+        }
+        failures += 1;
+        if failures == SEND_RETRIES {
+            // the receiver would count skipped counters as lost on the network
+            let given_up = &frames[next..];
+            given_up.iter().for_each(|_| accuracy.dropped());
+            let bytes = given_up.iter().map(|frame| frame.len() as u64).sum();
+            builder.rewind(given_up.len() as u32, bytes);
+            break;
+        }
+        sleep(RETRY_BACKOFF);
+    }
+    Ok(())
+}
+
+/// Ends the stream and waits for the receiver's final statistics
+fn finish_session(socket: &mut impl Sender) -> Option<Feedback> {
+    let deadline = Instant::now() + FINISH_TIMEOUT;
+    while Instant::now() < deadline {
+        socket.finish().ok()?;
+        let retry = Instant::now() + FINISH_RETRY;
+        while let Some(timeout) = retry.checked_duration_since(Instant::now()) {
+            match socket.recv_reply(timeout) {
+                Ok(Some(reply)) => match Feedback::from_bytes(reply) {
+                    Some(feedback) if feedback.last => return Some(feedback),
+                    _ => {}
+                },
+                Ok(None) => {}
+                Err(_) => return None,
+            }
+        }
     }
+    None
 }
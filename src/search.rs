@@ -0,0 +1,160 @@
+/// Module for RFC 2544 style throughput search
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{frames::FrameStatistics, rate::Bitrate, routines::SenderResult};
+
+/// Ethernet header and FCS, not present in the IP packet
+pub const ETHERNET_OVERHEAD: u16 = 18;
+
+/// Frame sizes recommended by RFC 2544 for Ethernet
+pub const DEFAULT_FRAME_SIZES: [u16; 7] = [64, 128, 256, 512, 1024, 1280, 1518];
+
+pub struct SearchParams {
+    /// Ethernet frame sizes to search throughput for
+    pub frame_sizes: Vec<u16>,
+    /// Highest rate to try, as Ethernet bitrate
+    pub max: Bitrate,
+    /// Highest loss still considered a pass, percent
+    pub threshold: f64,
+    /// Search stops when the range is narrower than this, percent of the rate
+    pub resolution: f64,
+    /// Pause between trials to let the device under test settle
+    pub gap: Duration,
+}
+
+/// Trial the device under test passed
+pub struct Passed {
+    pub fps: u64,
+    /// Frame loss of RFC 2544, percent of the frames sent
+    pub loss: f64,
+    pub statistics: FrameStatistics,
+}
+
+/// Throughput found for a frame size
+pub struct SearchResult {
+    pub frame_size: u16,
+    /// Highest passed frame rate
+    pub passed: Option<Passed>,
+}
+
+impl SearchResult {
+    pub fn bitrate(&self) -> Option<Bitrate> {
+        self.passed
+            .as_ref()
+            .map(|passed| Bitrate(passed.fps * u64::from(self.frame_size) * 8))
+    }
+}
+
+/// Percent of the `sent` frames which didn't arrive intact
+///
+/// Unlike the receiver's loss it covers frames lost after the last one that arrived.
+fn frame_loss(sent: u64, statistics: &FrameStatistics) -> f64 {
+    match sent {
+        0 => 0.,
+        sent => sent.saturating_sub(statistics.valid) as f64 / sent as f64 * 100.,
+    }
+}
+
+/// Binary searches the highest frame rate with loss under the threshold
+///
+/// `trial` runs a single trial of the frame size at the frame rate. Search
+/// starts with the maximum rate and stops early when interrupted.
+pub fn throughput_search(
+    params: &SearchParams,
+    mut trial: impl FnMut(u16, u64) -> Result<SenderResult>,
+) -> Result<Vec<SearchResult>> {
+    let mut results = vec![];
+    for &frame_size in &params.frame_sizes {
+        let max_fps = params.max.bytes_per_second() as u64 / u64::from(frame_size);
+        let (mut low, mut high) = (0, max_fps);
+        let mut fps = max_fps;
+        let mut result = SearchResult {
+            frame_size,
+            passed: None,
+        };
+        loop {
+            let SenderResult {
+                interrupted,
+                sent,
+                feedback,
+            } = trial(frame_size, fps)?;
+            if interrupted {
+                results.push(result);
+                return Ok(results);
+            }
+            std::thread::sleep(params.gap);
+            let loss = feedback.as_ref().map(|f| frame_loss(sent, &f.statistics));
+            match loss {
+                Some(loss) => println!("[{frame_size: >4}] {fps} fps: {loss:.3}% loss"),
+                None => println!("[{frame_size: >4}] {fps} fps: no results from the server"),
+            }
+            match (loss, feedback) {
+                (Some(loss), Some(feedback)) if loss <= params.threshold => {
+                    low = fps;
+                    result.passed = Some(Passed {
+                        fps,
+                        loss,
+                        statistics: feedback.statistics,
+                    });
+                }
+                _ => high = fps,
+            }
+            if (high - low) as f64 <= high as f64 * params.resolution / 100. || high - low <= 1 {
+                break;
+            }
+            fps = (low + high) / 2;
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+pub fn print_table(results: &[SearchResult]) {
+    println!("Frame size  Throughput, fps         Bitrate    Loss");
+    for result in results {
+        match (&result.passed, result.bitrate()) {
+            (Some(passed), Some(bitrate)) => println!(
+                "{: >10} {: >16} {bitrate: >15} {: >6.3}%",
+                result.frame_size, passed.fps, passed.loss
+            ),
+            _ => println!(
+                "{: >10} {: >16} {: >15} {: >7}",
+                result.frame_size, "-", "-", "-"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feedback::Feedback;
+
+    #[test]
+    fn test_search_converges() {
+        let params = SearchParams {
+            frame_sizes: vec![1000],
+            max: Bitrate(80_000_000),
+            threshold: 0.,
+            resolution: 0.1,
+            gap: Duration::ZERO,
+        };
+        // device forwards up to 3000 fps without loss, the frames above are lost at the end
+        // of the trial, where the receiver can't tell
+        let trial = |_, fps: u64| {
+            let mut stats = FrameStatistics::new(1);
+            stats.valid = fps.min(3000);
+            Ok(SenderResult {
+                interrupted: false,
+                sent: fps,
+                feedback: Some(Feedback::new(true, stats)),
+            })
+        };
+        let results = throughput_search(&params, trial).unwrap();
+        let passed = results[0].passed.as_ref().unwrap();
+        assert!((2990..=3000).contains(&passed.fps), "{}", passed.fps);
+        assert_eq!(passed.loss, 0.);
+    }
+}
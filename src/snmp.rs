@@ -0,0 +1,105 @@
+/// Module for the host's UDP counters of `/proc/net/snmp`
+use std::{fmt, io};
+
+/// UDP receive errors of the whole host
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UdpErrors {
+    /// Datagrams dropped as a socket buffer was full, `RcvbufErrors`
+    pub rcvbuf: u64,
+    /// Datagrams not delivered for any reason, these included, `InErrors`
+    pub input: u64,
+}
+
+impl UdpErrors {
+    /// Current counters, Linux only
+    pub fn read() -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            Self::parse(&std::fs::read_to_string("/proc/net/snmp")?).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no UDP counters in /proc/net/snmp",
+                )
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "/proc/net/snmp is Linux only",
+        ))
+    }
+
+    /// Counters of the `Udp:` lines, one of names and one of values
+    fn parse(snmp: &str) -> Option<Self> {
+        let mut lines = snmp.lines().filter_map(|line| line.strip_prefix("Udp: "));
+        let (names, values) = (lines.next()?, lines.next()?);
+        let counter = |name| {
+            let (_, value) = names
+                .split_whitespace()
+                .zip(values.split_whitespace())
+                .find(|(n, _)| *n == name)?;
+            value.parse().ok()
+        };
+        Some(Self {
+            rcvbuf: counter("RcvbufErrors")?,
+            input: counter("InErrors")?,
+        })
+    }
+}
+
+impl fmt::Display for UdpErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} UDP receive buffer errors, {} input errors",
+            self.rcvbuf, self.input
+        )
+    }
+}
+
+/// Follows the host's UDP errors, telling how many more there are on every poll
+pub struct HostErrors {
+    last: UdpErrors,
+}
+
+impl HostErrors {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            last: UdpErrors::read()?,
+        })
+    }
+
+    /// Errors since the last poll, if there were any
+    pub fn poll(&mut self) -> Option<UdpErrors> {
+        let now = UdpErrors::read().ok()?;
+        let new = UdpErrors {
+            rcvbuf: now.rcvbuf.saturating_sub(self.last.rcvbuf),
+            input: now.input.saturating_sub(self.last.input),
+        };
+        self.last = now;
+        (new != UdpErrors::default()).then_some(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let snmp = "\
+Udp: InDatagrams NoPorts InErrors OutDatagrams RcvbufErrors SndbufErrors InCsumErrors
+Udp: 1500 3 42 1200 40 0 2
+UdpLite: InDatagrams NoPorts InErrors OutDatagrams RcvbufErrors SndbufErrors InCsumErrors
+UdpLite: 0 0 7 0 7 0 0
+";
+        assert_eq!(
+            UdpErrors::parse(snmp),
+            Some(UdpErrors {
+                rcvbuf: 40,
+                input: 42
+            })
+        );
+        assert_eq!(UdpErrors::parse("Ip: Forwarding\nIp: 1\n"), None);
+    }
+}
@@ -0,0 +1,242 @@
+use std::time::{Duration, Instant};
+
+use super::Limiter;
+use crate::{
+    clock::{Clock, SystemClock},
+    feedback::Feedback,
+    frames::FrameStatistics,
+    rate::{Bitrate, Rate},
+};
+
+/// Loss-adaptive limiter, not unlike TCP congestion control
+///
+/// Rate grows by `increase` every second while the receiver reports no loss
+/// and is multiplied by `decrease` on every report with loss or when reports stop.
+/// Until the first report arrives the rate is held, as the receiver may not send any.
+pub struct AimdLimiter<C: Clock = SystemClock> {
+    /// Current rate, bits per second
+    rate: f64,
+    /// Additive increase, bits per second every second
+    increase: f64,
+    /// Multiplicative decrease factor
+    decrease: f64,
+    /// Rate of one frame per second, lower bound of the rate
+    min_rate: f64,
+    frame_size: f64,
+    next_departure: Instant,
+    last_feedback: Instant,
+    /// A report has arrived, so missing ones mean a stall
+    heard: bool,
+    /// The user was told that no reports arrive
+    warned: bool,
+    /// Sending is paused, so reports without new frames aren't a stall
+    paused: bool,
+    previous: Option<FrameStatistics>,
+    /// Moving average of the rate around the moments of loss
+    converged: Option<f64>,
+    clock: C,
+}
+
+impl AimdLimiter {
+    pub fn new(start: Bitrate, increase: Bitrate, decrease: f64, frame_size: u16) -> Self {
+        Self::with_clock(start, increase, decrease, frame_size, SystemClock)
+    }
+}
+
+impl<C: Clock> AimdLimiter<C> {
+    /// Longest stall the limiter would catch up after
+    const MAX_DEBT: Duration = Duration::from_millis(100);
+
+    /// Missing reports for that long are treated as loss
+    const FEEDBACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Weight of the newest loss event in the converged rate
+    const CONVERGED_WEIGHT: f64 = 0.25;
+
+    pub fn with_clock(
+        start: Bitrate,
+        increase: Bitrate,
+        decrease: f64,
+        frame_size: u16,
+        clock: C,
+    ) -> Self {
+        let now = clock.now();
+        let min_rate = f64::from(frame_size) * 8.;
+        Self {
+            rate: (start.0 as f64).max(min_rate),
+            increase: increase.0 as f64,
+            decrease: decrease.clamp(0., 1.),
+            min_rate,
+            frame_size: frame_size.into(),
+            next_departure: now,
+            last_feedback: now,
+            heard: false,
+            warned: false,
+            paused: false,
+            previous: None,
+            converged: None,
+            clock,
+        }
+    }
+
+    /// Multiplies the rate by `decrease`, telling the `reason`
+    fn cut(&mut self, reason: &str) {
+        let cut = (self.rate * self.decrease).max(self.min_rate);
+        // rate oscillates between the peak and the cut
+        let average = (self.rate + cut) / 2.;
+        let converged = match self.converged {
+            Some(converged) => converged + (average - converged) * Self::CONVERGED_WEIGHT,
+            None => average,
+        };
+        self.converged = Some(converged);
+        println!(
+            "{reason}, rate {} -> {}, converged rate {}",
+            Bitrate(self.rate as u64),
+            Bitrate(cut as u64),
+            Bitrate(converged as u64)
+        );
+        self.rate = cut;
+    }
+}
+
+impl<C: Clock> Limiter for AimdLimiter<C> {
+    fn sleep_interval(&mut self) -> Duration {
+        let now = self.clock.now();
+        if now - self.last_feedback > Self::FEEDBACK_TIMEOUT {
+            self.last_feedback = now;
+            if self.heard {
+                self.cut("No feedback");
+            } else if !self.warned {
+                eprintln!(
+                    "No feedback from the server, holding the rate: \
+                     AIMD needs the server started with --feedback"
+                );
+                self.warned = true;
+            }
+        }
+        let gap = Duration::from_secs_f64(self.frame_size * 8. / self.rate);
+        self.next_departure = self.next_departure.max(now - Self::MAX_DEBT) + gap;
+        self.next_departure.saturating_duration_since(now)
+    }
+
+    fn target(&self) -> Option<Bitrate> {
+        Some(Bitrate(self.rate as u64))
+    }
+
+    fn feedback(&mut self, feedback: &Feedback) {
+        let now = self.clock.now();
+        let elapsed = now - self.last_feedback;
+        self.last_feedback = now;
+        self.heard = true;
+        let stats = &feedback.statistics;
+        let Some(previous) = self
+            .previous
+            .replace(stats.clone())
+            .filter(|previous| previous.session_id == stats.session_id)
+        else {
+            return;
+        };
+        let bad = |s: &FrameStatistics| s.lost + s.invalid + s.internally_bad;
+        let received = stats.total() - bad(stats);
+        // nothing arrived, though a couple of frames should have
+        let expected = elapsed.as_secs_f64() * self.rate / (self.frame_size * 8.);
+        let stalled = received == previous.total() - bad(&previous) && expected >= 2.;
+        if bad(stats) > bad(&previous) {
+            self.cut("Loss reported");
+        } else if stalled && !self.paused {
+            self.cut("Nothing received");
+        } else if !self.paused {
+            self.rate += self.increase * elapsed.as_secs_f64();
+        }
+    }
+
+    fn summary(&self) -> Option<String> {
+        let converged = self.converged.unwrap_or(self.rate);
+        Some(format!("Converged rate: {}", Bitrate(converged as u64)))
+    }
+
+    /// Restarts probing from `rate`
+    fn set_rate(&mut self, rate: Rate) -> bool {
+        self.frame_size = rate.frame_size().into();
+        self.min_rate = self.frame_size * 8.;
+        self.rate = (rate.bitrate().0 as f64).max(self.min_rate);
+        true
+    }
+
+    fn pause(&mut self, paused: bool) {
+        self.paused = paused;
+        // the pause doesn't count as missing reports
+        self.last_feedback = self.clock.now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn feedback(valid: u64, lost: u64) -> Feedback {
+        let mut stats = FrameStatistics::new(1);
+        stats.valid = valid;
+        stats.lost = lost;
+        Feedback::new(false, stats)
+    }
+
+    #[test]
+    fn test_cuts_on_loss() {
+        let clock = MockClock::new();
+        let mut limiter = AimdLimiter::with_clock(
+            Bitrate(10_000_000),
+            Bitrate(1_000_000),
+            0.5,
+            1000,
+            clock.clone(),
+        );
+        limiter.feedback(&feedback(100, 0));
+        clock.advance(Duration::from_secs(1));
+        limiter.feedback(&feedback(200, 0));
+        assert_eq!(limiter.target(), Some(Bitrate(11_000_000)));
+
+        clock.advance(Duration::from_secs(1));
+        limiter.feedback(&feedback(300, 5));
+        assert_eq!(limiter.target(), Some(Bitrate(5_500_000)));
+    }
+
+    #[test]
+    fn test_missing_feedback() {
+        let clock = MockClock::new();
+        let mut limiter =
+            AimdLimiter::with_clock(Bitrate(10_000_000), Bitrate(0), 0.5, 1000, clock.clone());
+        // the server may not send reports at all
+        clock.advance(Duration::from_secs(2));
+        limiter.sleep_interval();
+        assert_eq!(limiter.target(), Some(Bitrate(10_000_000)));
+
+        limiter.feedback(&feedback(100, 0));
+        clock.advance(Duration::from_secs(2));
+        limiter.sleep_interval();
+        assert_eq!(limiter.target(), Some(Bitrate(5_000_000)));
+    }
+
+    #[test]
+    fn test_pause() {
+        let clock = MockClock::new();
+        let mut limiter =
+            AimdLimiter::with_clock(Bitrate(10_000_000), Bitrate(0), 0.5, 1000, clock.clone());
+        limiter.feedback(&feedback(100, 0));
+        limiter.pause(true);
+        for _ in 0..3 {
+            clock.advance(Duration::from_secs(1));
+            limiter.feedback(&feedback(100, 0));
+        }
+        clock.advance(Duration::from_secs(5));
+        limiter.pause(false);
+        limiter.sleep_interval();
+        assert_eq!(limiter.target(), Some(Bitrate(10_000_000)));
+
+        // reports without new frames are a stall again
+        clock.advance(Duration::from_secs(1));
+        limiter.feedback(&feedback(100, 0));
+        assert_eq!(limiter.target(), Some(Bitrate(5_000_000)));
+    }
+}
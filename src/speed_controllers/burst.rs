@@ -4,41 +4,45 @@ use std::{
 };
 
 use super::Limiter;
+use crate::{
+    clock::{Clock, SystemClock},
+    rate::{Bitrate, Rate},
+};
 
 /// Bursty Limiter for optimal CPU usage, not unlike iperf
-pub struct BurstLimiter {
+pub struct BurstLimiter<C: Clock = SystemClock> {
     burst_window: Duration,
     burst_count: u64,
     state: Option<(Instant, Range<u64>)>,
     disabled: bool,
+    target: Option<Bitrate>,
+    dynamic_window: bool,
+    clock: C,
 }
 
 impl BurstLimiter {
-    pub fn new(speed: u32, mtu: u16, dynamic_window: bool) -> Self {
-        let frames_per_second = (Into::<u64>::into(speed) * (1024 / 8)) / Into::<u64>::into(mtu);
-        let window: u64 = if dynamic_window {
-            match frames_per_second {
-                0 => 1,
-                1..=99 => 1_000_000,
-                1..=999 => 100_000,
-                1000..=9999 => 10_000,
-                10000..=99999 => 100,
-                100000.. => 1,
-            }
-        } else {
-            1_000_000
-        };
-        println!("frames_per_second={frames_per_second}, window={window}");
-        Self {
-            burst_window: Duration::from_micros(window),
-            burst_count: frames_per_second / (1_000_000 / window),
+    pub fn new(rate: Rate, dynamic_window: bool) -> Self {
+        Self::with_clock(rate, dynamic_window, SystemClock)
+    }
+}
+
+impl<C: Clock> BurstLimiter<C> {
+    pub fn with_clock(rate: Rate, dynamic_window: bool, clock: C) -> Self {
+        let mut limiter = Self {
+            burst_window: Duration::ZERO,
+            burst_count: 1,
             state: None,
-            disabled: if speed == 0 { true } else { false },
-        }
+            disabled: true,
+            target: None,
+            dynamic_window,
+            clock,
+        };
+        limiter.set_rate(rate);
+        limiter
     }
 }
 
-impl Limiter for BurstLimiter {
+impl<C: Clock> Limiter for BurstLimiter<C> {
     fn sleep_interval(&mut self) -> Duration {
         if self.disabled {
             return Duration::ZERO; // no sleep while disabled
@@ -46,15 +50,47 @@ impl Limiter for BurstLimiter {
         let (time, mut range) = self
             .state
             .take()
-            .unwrap_or((Instant::now(), 0..self.burst_count));
-        if let Some(_) = range.next() {
+            // the frame already sent opens the burst
+            .unwrap_or((self.clock.now(), 1..self.burst_count));
+        if range.next().is_some() {
             self.state = Some((time, range));
             Duration::ZERO // no sleep while some bursts left
         } else {
             self.state = None;
             self.burst_window
-                .checked_sub(time.elapsed())
+                .checked_sub(self.clock.since(time))
                 .unwrap_or(Duration::ZERO) // sleep all the remaining burst time
         }
     }
+
+    fn target(&self) -> Option<Bitrate> {
+        self.target
+    }
+
+    fn set_rate(&mut self, rate: Rate) -> bool {
+        let frames_per_second = rate.frames_per_second() as u64;
+        let window: u64 = if self.dynamic_window {
+            match frames_per_second {
+                0 => 1,
+                1..=99 => 1_000_000,
+                100..=999 => 100_000,
+                1000..=9999 => 10_000,
+                10000.. => 100,
+            }
+        } else {
+            1_000_000
+        };
+        // whole frames per burst, window is stretched to fit them exactly
+        let burst_count = (rate.frames_per_second() * window as f64 / 1_000_000.).max(1.) as u64;
+        self.burst_window = if rate.is_unlimited() {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(burst_count as f64 / rate.frames_per_second())
+        };
+        self.burst_count = burst_count;
+        self.state = None;
+        self.disabled = rate.is_unlimited();
+        self.target = (!rate.is_unlimited()).then(|| rate.bitrate());
+        true
+    }
 }
@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use super::Limiter;
+use crate::{
+    args::Pacing,
+    clock::{Clock, SystemClock},
+    feedback::Feedback,
+    protocols::{ProtoError, Sender},
+    rate::{Bitrate, Rate},
+};
+
+/// How the kernel paces a socket, kept to follow rate changes
+#[derive(Clone)]
+pub struct KernelPacing {
+    pub pacing: Pacing,
+    /// Bytes of a frame on the wire besides the record
+    pub overhead: u16,
+}
+
+impl KernelPacing {
+    /// Has the kernel pace `socket` at `rate` of records
+    pub fn apply(&self, socket: &mut impl Sender, rate: &Rate) -> Result<(), ProtoError> {
+        let wire = rate.with_frame_size(rate.frame_size() + self.overhead);
+        match self.pacing {
+            Pacing::USER => Ok(()),
+            Pacing::FQ => socket.set_pacing_rate(&wire),
+            Pacing::TXTIME => socket.set_txtime(&wire),
+        }
+    }
+}
+
+/// Limiter for sockets paced by the kernel
+///
+/// Doesn't sleep, the socket blocks until the kernel lets the frames out.
+/// Switches to the `fallback` limiter if the frames leave noticeably faster
+/// than the target, e.g. when the interface has no fq qdisc.
+pub struct KernelLimiter<C: Clock = SystemClock> {
+    frames_per_second: f64,
+    /// Frames the socket may take at once, over the target rate
+    burst: f64,
+    start: Option<Instant>,
+    sent: u64,
+    fallback: Box<dyn Limiter>,
+    fell_back: bool,
+    clock: C,
+}
+
+impl KernelLimiter {
+    pub fn new(rate: Rate, fallback: Box<dyn Limiter>) -> Self {
+        Self::with_clock(rate, fallback, SystemClock)
+    }
+}
+
+impl<C: Clock> KernelLimiter<C> {
+    /// Bytes the socket may take at once: send buffer and initial window
+    const BURST: u32 = 1 << 20;
+
+    /// Part of the target rate, by which the kernel may exceed it
+    const TOLERANCE: f64 = 0.1;
+
+    pub fn with_clock(rate: Rate, fallback: Box<dyn Limiter>, clock: C) -> Self {
+        Self {
+            frames_per_second: rate.frames_per_second(),
+            burst: Self::burst(&rate),
+            start: None,
+            sent: 0,
+            fallback,
+            fell_back: false,
+            clock,
+        }
+    }
+
+    fn burst(rate: &Rate) -> f64 {
+        f64::from(Self::BURST / u32::from(rate.frame_size()))
+    }
+
+    /// Whether frames are sent faster than the kernel should allow
+    fn overshoots(&self, elapsed: Duration) -> bool {
+        let allowed = self.frames_per_second * elapsed.as_secs_f64() * (1. + Self::TOLERANCE);
+        self.sent as f64 > allowed + self.burst
+    }
+}
+
+impl<C: Clock> Limiter for KernelLimiter<C> {
+    fn sleep_interval(&mut self) -> Duration {
+        if self.fell_back {
+            return self.fallback.sleep_interval();
+        }
+        let start = *self.start.get_or_insert_with(|| self.clock.now());
+        self.sent += 1;
+        if self.overshoots(self.clock.since(start)) {
+            eprintln!(
+                "Kernel doesn't pace the socket, is the fq qdisc set up? Pacing in user space"
+            );
+            self.fell_back = true;
+        }
+        Duration::ZERO
+    }
+
+    fn target(&self) -> Option<Bitrate> {
+        self.fallback.target()
+    }
+
+    fn feedback(&mut self, feedback: &Feedback) {
+        self.fallback.feedback(feedback)
+    }
+
+    fn summary(&self) -> Option<String> {
+        self.fallback.summary()
+    }
+
+    /// Follows `rate` with the fallback, the socket has to be paced again by the caller
+    fn set_rate(&mut self, rate: Rate) -> bool {
+        if !self.fallback.set_rate(rate) {
+            return false;
+        }
+        self.frames_per_second = rate.frames_per_second();
+        self.burst = Self::burst(&rate);
+        // overshooting is measured from the change
+        self.start = None;
+        self.sent = 0;
+        true
+    }
+
+    fn kernel_paced(&self) -> bool {
+        !self.fell_back
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speed_controllers::StaticLimiter;
+
+    #[test]
+    fn test_falls_back_when_unpaced() {
+        let rate = Rate::packets(100, 1 << 14);
+        let mut limiter = KernelLimiter::new(rate, Box::new(StaticLimiter::new(rate)));
+        // Frames leave as fast as they are made, way over 100 fps
+        for _ in 0..65 {
+            assert_eq!(limiter.sleep_interval(), Duration::ZERO);
+        }
+        assert_eq!(limiter.sleep_interval(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_follows_rate_change() {
+        let rate = Rate::packets(100, 1 << 14);
+        let mut limiter = KernelLimiter::new(rate, Box::new(StaticLimiter::new(rate)));
+        for _ in 0..60 {
+            limiter.sleep_interval();
+        }
+        assert!(limiter.set_rate(Rate::packets(1000, 1 << 14)));
+        // the count starts over, the burst alone doesn't overshoot
+        for _ in 0..64 {
+            assert_eq!(limiter.sleep_interval(), Duration::ZERO);
+        }
+        assert!(limiter.kernel_paced());
+    }
+}
@@ -10,8 +10,108 @@ pub use burst::BurstLimiter;
 mod overtime;
 pub use overtime::OverTimeLimiter;
 
+mod aimd;
+pub use aimd::AimdLimiter;
+
+mod kernel;
+pub use kernel::{KernelLimiter, KernelPacing};
+
+mod profile;
+pub use profile::{Profile, ProfileLimiter};
+
+mod random;
+pub use random::{RandomLimiter, Traffic};
+
+mod trace;
+pub use trace::TraceLimiter;
+
+mod token_bucket;
+pub use token_bucket::{BucketDepth, TokenBucketLimiter};
+
+#[cfg(test)]
+mod simulation;
+
 use std::time::Duration;
 
+use crate::{
+    feedback::Feedback,
+    rate::{Bitrate, Rate},
+};
+
 pub trait Limiter {
     fn sleep_interval(&mut self) -> Duration;
+
+    /// Bitrate the limiter is aiming for at the moment, if it has one
+    fn target(&self) -> Option<Bitrate> {
+        None
+    }
+
+    /// Adjusts to statistics reported by the receiver
+    fn feedback(&mut self, _feedback: &Feedback) {}
+
+    /// Outcome of the limiting to print at the end of the session
+    fn summary(&self) -> Option<String> {
+        None
+    }
+
+    /// Record size of the next frame, if the limiter dictates it
+    fn frame_size(&self) -> Option<u16> {
+        None
+    }
+
+    /// Whether the limiter has nothing more to send
+    fn is_done(&self) -> bool {
+        false
+    }
+
+    /// Switches to `rate` on the fly, false if the limiter doesn't follow a set rate
+    fn set_rate(&mut self, _rate: Rate) -> bool {
+        false
+    }
+
+    /// Whether the kernel paces the socket, so it has to be told about rate changes
+    fn kernel_paced(&self) -> bool {
+        false
+    }
+
+    /// Sending was paused or resumed by the user
+    fn pause(&mut self, _paused: bool) {}
+}
+
+impl<L: Limiter + ?Sized> Limiter for Box<L> {
+    fn sleep_interval(&mut self) -> Duration {
+        (**self).sleep_interval()
+    }
+
+    fn target(&self) -> Option<Bitrate> {
+        (**self).target()
+    }
+
+    fn feedback(&mut self, feedback: &Feedback) {
+        (**self).feedback(feedback)
+    }
+
+    fn summary(&self) -> Option<String> {
+        (**self).summary()
+    }
+
+    fn frame_size(&self) -> Option<u16> {
+        (**self).frame_size()
+    }
+
+    fn is_done(&self) -> bool {
+        (**self).is_done()
+    }
+
+    fn set_rate(&mut self, rate: Rate) -> bool {
+        (**self).set_rate(rate)
+    }
+
+    fn kernel_paced(&self) -> bool {
+        (**self).kernel_paced()
+    }
+
+    fn pause(&mut self, paused: bool) {
+        (**self).pause(paused)
+    }
 }
@@ -1,33 +1,49 @@
 use std::time::{Duration, Instant};
 
 use super::Limiter;
+use crate::{
+    clock::{Clock, SystemClock},
+    rate::Rate,
+};
 
 /// Toy limiter for testing
-pub struct OverTimeLimiter {
-    speed: u32,
-    mtu: u16,
+pub struct OverTimeLimiter<C: Clock = SystemClock> {
+    rate: Rate,
     time: Instant,
+    clock: C,
 }
 
 impl OverTimeLimiter {
-    pub fn new(speed: u32, mtu: u16) -> Self {
+    pub fn new(rate: Rate) -> Self {
+        Self::with_clock(rate, SystemClock)
+    }
+}
+
+impl<C: Clock> OverTimeLimiter<C> {
+    pub fn with_clock(rate: Rate, clock: C) -> Self {
         Self {
-            speed,
-            mtu,
-            time: Instant::now(),
+            rate,
+            time: clock.now(),
+            clock,
         }
     }
 }
 
-impl Limiter for OverTimeLimiter {
+impl<C: Clock> Limiter for OverTimeLimiter<C> {
     fn sleep_interval(&mut self) -> Duration {
-        let time_elapsed = self.time.elapsed().as_secs();
-        let limit_magnitude = 50;
-        let mut speed_reduced =
-            ((self.speed as u64 - time_elapsed * limit_magnitude) * 1024 / 8) / self.mtu as u64;
+        let time_elapsed = self.clock.since(self.time).as_secs();
+        // bits per second, reduced every second
+        let limit_magnitude = 50_000;
+        let mut speed_reduced = ((self
+            .rate
+            .bitrate()
+            .bits_per_second()
+            .saturating_sub(time_elapsed * limit_magnitude))
+            / 8)
+            / u64::from(self.rate.frame_size());
         if speed_reduced == 0 {
             speed_reduced = 1;
-            self.time = Instant::now();
+            self.time = self.clock.now();
         }
         Duration::from_micros(1_000_000 / speed_reduced)
     }
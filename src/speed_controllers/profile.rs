@@ -0,0 +1,261 @@
+use std::{
+    fs,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use super::Limiter;
+use crate::{
+    clock::{Clock, SystemClock},
+    rate::Bitrate,
+};
+
+/// Shape of the target bitrate over time
+#[derive(Clone, Debug, PartialEq)]
+pub enum Profile {
+    /// Linear ramp from one rate to another, then hold
+    Ramp {
+        from: Bitrate,
+        to: Bitrate,
+        duration: Duration,
+    },
+    /// Staircase from one rate to another, holding every step for `dwell`
+    Steps {
+        from: Bitrate,
+        to: Bitrate,
+        step: Bitrate,
+        dwell: Duration,
+    },
+    /// Repeated linear ramp
+    Sawtooth {
+        from: Bitrate,
+        to: Bitrate,
+        period: Duration,
+    },
+    /// Linear interpolation between `(time, rate)` points sorted by time
+    Points(Vec<(Duration, Bitrate)>),
+}
+
+/// Linear interpolation between `from` and `to`, `pos` is in range 0..=1
+fn lerp(from: Bitrate, to: Bitrate, pos: f64) -> Bitrate {
+    let (from, to) = (from.0 as f64, to.0 as f64);
+    Bitrate((from + (to - from) * pos.clamp(0., 1.)).round() as u64)
+}
+
+impl Profile {
+    /// Target rate `elapsed` since the start
+    pub fn rate_at(&self, elapsed: Duration) -> Bitrate {
+        let t = elapsed.as_secs_f64();
+        match self {
+            Self::Ramp { from, to, duration } => lerp(*from, *to, t / duration.as_secs_f64()),
+            Self::Steps {
+                from,
+                to,
+                step,
+                dwell,
+            } => {
+                let steps = (t / dwell.as_secs_f64()).floor() as u64;
+                let rate = from.0.saturating_add(steps.saturating_mul(step.0));
+                Bitrate(if from <= to {
+                    rate.min(to.0)
+                } else {
+                    from.0
+                        .saturating_sub(steps.saturating_mul(step.0))
+                        .max(to.0)
+                })
+            }
+            Self::Sawtooth { from, to, period } => {
+                lerp(*from, *to, t % period.as_secs_f64() / period.as_secs_f64())
+            }
+            Self::Points(points) => {
+                let next = points.partition_point(|(time, _)| *time <= elapsed);
+                match (
+                    next.checked_sub(1).map(|i| points[i]),
+                    points.get(next).copied(),
+                ) {
+                    (Some((t0, r0)), Some((t1, r1))) => lerp(
+                        r0,
+                        r1,
+                        (elapsed - t0).as_secs_f64() / (t1 - t0).as_secs_f64(),
+                    ),
+                    (Some((_, rate)), None) | (None, Some((_, rate))) => rate,
+                    (None, None) => Bitrate(0),
+                }
+            }
+        }
+    }
+
+    /// Parses profile file with a `<seconds> <rate>` pair on every line
+    ///
+    /// Empty lines and lines starting with `#` are skipped.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let mut points = vec![];
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |e: String| format!("{path}:{}: {e}", n + 1);
+            let (time, rate) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| err("expected `<seconds> <rate>`".into()))?;
+            points.push((
+                parse_secs(time).map_err(err)?,
+                rate.trim().parse().map_err(err)?,
+            ));
+        }
+        if points.is_empty() {
+            return Err(format!("{path}: no points in profile"));
+        }
+        points.sort_by_key(|(time, _)| *time);
+        Ok(Self::Points(points))
+    }
+}
+
+/// Parses seconds with an optional `s` suffix
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs = s.strip_suffix('s').unwrap_or(s);
+    secs.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("invalid duration `{s}`"))
+}
+
+/// Parses `ramp:FROM:TO:SECS`, `steps:FROM:TO:STEP:DWELL`,
+/// `sawtooth:FROM:TO:PERIOD` or `file:PATH`
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("file:") {
+            return Self::from_file(path);
+        }
+        let parts: Vec<&str> = s.split(':').collect();
+        let rate = |i: usize| parts[i].parse::<Bitrate>();
+        let positive = |i: usize| {
+            parse_secs(parts[i]).and_then(|secs| match secs.is_zero() {
+                true => Err(format!("duration `{}` has to be positive", parts[i])),
+                false => Ok(secs),
+            })
+        };
+        match parts[..] {
+            ["ramp", _, _, _] => Ok(Self::Ramp {
+                from: rate(1)?,
+                to: rate(2)?,
+                duration: positive(3)?,
+            }),
+            ["steps", _, _, _, _] => {
+                let (from, to, step) = (rate(1)?, rate(2)?, rate(3)?);
+                if step.0 == 0 || from == to {
+                    return Err(format!(
+                        "steps need a positive step between two rates in `{s}`"
+                    ));
+                }
+                Ok(Self::Steps {
+                    from,
+                    to,
+                    step,
+                    dwell: positive(4)?,
+                })
+            }
+            ["sawtooth", _, _, _] => Ok(Self::Sawtooth {
+                from: rate(1)?,
+                to: rate(2)?,
+                period: positive(3)?,
+            }),
+            _ => Err(format!("invalid profile `{s}`")),
+        }
+    }
+}
+
+/// Limiter following a bitrate profile
+///
+/// Frames are paced by their departure schedule, which is advanced with the
+/// current target rate, so the limiter catches up after short stalls.
+pub struct ProfileLimiter<C: Clock = SystemClock> {
+    profile: Profile,
+    frame_size: f64,
+    start: Instant,
+    next_departure: Instant,
+    target: Bitrate,
+    clock: C,
+}
+
+impl ProfileLimiter {
+    pub fn new(profile: Profile, frame_size: u16) -> Self {
+        Self::with_clock(profile, frame_size, SystemClock)
+    }
+}
+
+impl<C: Clock> ProfileLimiter<C> {
+    /// Longest stall the limiter would catch up after
+    const MAX_DEBT: Duration = Duration::from_millis(100);
+
+    /// Poll interval while the profile is at zero rate
+    const IDLE: Duration = Duration::from_millis(10);
+
+    pub fn with_clock(profile: Profile, frame_size: u16, clock: C) -> Self {
+        let now = clock.now();
+        Self {
+            target: profile.rate_at(Duration::ZERO),
+            profile,
+            frame_size: frame_size.into(),
+            start: now,
+            next_departure: now,
+            clock,
+        }
+    }
+}
+
+impl<C: Clock> Limiter for ProfileLimiter<C> {
+    fn sleep_interval(&mut self) -> Duration {
+        let now = self.clock.now();
+        self.target = self.profile.rate_at(now - self.start);
+        if self.target.0 == 0 {
+            self.next_departure = now;
+            return Self::IDLE;
+        }
+        let gap = Duration::from_secs_f64(self.frame_size / self.target.bytes_per_second());
+        self.next_departure = self.next_departure.max(now - Self::MAX_DEBT) + gap;
+        self.next_departure.saturating_duration_since(now)
+    }
+
+    fn target(&self) -> Option<Bitrate> {
+        Some(self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn test_profiles() {
+        let ramp: Profile = "ramp:10M:20M:10".parse().unwrap();
+        assert_eq!(ramp.rate_at(secs(5.)), Bitrate(15_000_000));
+        assert_eq!(ramp.rate_at(secs(60.)), Bitrate(20_000_000));
+
+        let steps: Profile = "steps:1M:3M:1M:2s".parse().unwrap();
+        assert_eq!(steps.rate_at(secs(1.9)), Bitrate(1_000_000));
+        assert_eq!(steps.rate_at(secs(2.)), Bitrate(2_000_000));
+        assert_eq!(steps.rate_at(secs(100.)), Bitrate(3_000_000));
+
+        let saw: Profile = "sawtooth:0:10M:10".parse().unwrap();
+        assert_eq!(saw.rate_at(secs(12.5)), Bitrate(2_500_000));
+
+        let points = Profile::Points(vec![(secs(1.), Bitrate(100)), (secs(3.), Bitrate(300))]);
+        assert_eq!(points.rate_at(secs(0.)), Bitrate(100));
+        assert_eq!(points.rate_at(secs(2.)), Bitrate(200));
+        assert_eq!(points.rate_at(secs(4.)), Bitrate(300));
+
+        assert!("ramp:1M:2M:0".parse::<Profile>().is_err());
+        assert!("steps:1M:3M:0:1".parse::<Profile>().is_err());
+        assert!("steps:1M:1M:1M:1".parse::<Profile>().is_err());
+        assert!("square:1M:2M:1".parse::<Profile>().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::Limiter;
+use crate::{
+    clock::{Clock, SystemClock},
+    rate::{Bitrate, Rate},
+};
+
+/// Distribution of inter-departure times
+#[derive(Clone, Debug, PartialEq)]
+pub enum Traffic {
+    /// Exponential gaps, frames form a Poisson process
+    Exponential,
+    /// Heavy-tailed Pareto gaps with the shape parameter above 1
+    Pareto(f64),
+    /// Exponentially distributed on and off periods with the given means,
+    /// evenly spaced frames at the peak rate while on
+    OnOff { on: Duration, off: Duration },
+}
+
+/// Parses `exp`, `pareto:SHAPE` or `onoff:ON:OFF` with periods in seconds
+impl FromStr for Traffic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let float = |i: usize| {
+            parts[i]
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value > 0.)
+                .ok_or_else(|| format!("invalid number `{}` in `{s}`", parts[i]))
+        };
+        match parts[..] {
+            ["exp"] => Ok(Self::Exponential),
+            ["pareto", _] => match float(1)? {
+                shape if shape > 1. => Ok(Self::Pareto(shape)),
+                _ => Err(format!("pareto shape has to be above 1 in `{s}`")),
+            },
+            ["onoff", _, _] => Ok(Self::OnOff {
+                on: Duration::from_secs_f64(float(1)?),
+                off: Duration::from_secs_f64(float(2)?),
+            }),
+            _ => Err(format!("invalid traffic model `{s}`")),
+        }
+    }
+}
+
+/// Limiter with random inter-departure times averaging to the target rate
+pub struct RandomLimiter<C: Clock = SystemClock> {
+    traffic: Traffic,
+    rng: StdRng,
+    /// Mean gap between frames, seconds
+    mean_gap: f64,
+    /// Time left in the current on period
+    on_left: Duration,
+    next_departure: Instant,
+    disabled: bool,
+    target: Option<Bitrate>,
+    clock: C,
+}
+
+impl RandomLimiter {
+    pub fn new(rate: Rate, traffic: Traffic, seed: u64) -> Self {
+        Self::with_clock(rate, traffic, seed, SystemClock)
+    }
+}
+
+impl<C: Clock> RandomLimiter<C> {
+    /// Longest stall the limiter would catch up after
+    const MAX_DEBT: Duration = Duration::from_millis(100);
+
+    pub fn with_clock(rate: Rate, traffic: Traffic, seed: u64, clock: C) -> Self {
+        Self {
+            traffic,
+            rng: StdRng::seed_from_u64(seed),
+            mean_gap: 1. / rate.frames_per_second(),
+            on_left: Duration::ZERO,
+            next_departure: clock.now(),
+            disabled: rate.is_unlimited(),
+            target: (!rate.is_unlimited()).then(|| rate.bitrate()),
+            clock,
+        }
+    }
+
+    /// Exponentially distributed value with the given mean
+    fn exponential(&mut self, mean: f64) -> f64 {
+        // 1 - U is in (0, 1], so the logarithm is finite
+        -mean * (1. - self.rng.gen::<f64>()).ln()
+    }
+
+    /// Gap between the last frame and the next one
+    fn next_gap(&mut self) -> Duration {
+        match self.traffic {
+            Traffic::Exponential => Duration::from_secs_f64(self.exponential(self.mean_gap)),
+            Traffic::Pareto(shape) => {
+                let scale = self.mean_gap * (shape - 1.) / shape;
+                let uniform = 1. - self.rng.gen::<f64>();
+                Duration::from_secs_f64(scale / uniform.powf(1. / shape))
+            }
+            Traffic::OnOff { on, off } => {
+                // peak rate keeps the average at the target
+                let duty = on.as_secs_f64() / (on + off).as_secs_f64();
+                let peak_gap = Duration::from_secs_f64(self.mean_gap * duty);
+                let mut gap = peak_gap;
+                while self.on_left < peak_gap {
+                    let off = self.exponential(off.as_secs_f64());
+                    let on = self.exponential(on.as_secs_f64());
+                    gap += Duration::from_secs_f64(off);
+                    self.on_left += Duration::from_secs_f64(on);
+                }
+                self.on_left -= peak_gap;
+                gap
+            }
+        }
+    }
+}
+
+impl<C: Clock> Limiter for RandomLimiter<C> {
+    fn sleep_interval(&mut self) -> Duration {
+        if self.disabled {
+            return Duration::ZERO;
+        }
+        let now = self.clock.now();
+        self.next_departure = self.next_departure.max(now - Self::MAX_DEBT) + self.next_gap();
+        self.next_departure.saturating_duration_since(now)
+    }
+
+    fn target(&self) -> Option<Bitrate> {
+        self.target
+    }
+
+    fn set_rate(&mut self, rate: Rate) -> bool {
+        self.mean_gap = 1. / rate.frames_per_second();
+        self.disabled = rate.is_unlimited();
+        self.target = (!rate.is_unlimited()).then(|| rate.bitrate());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean_gap(traffic: &str) -> f64 {
+        let rate = Rate::packets(1000, 1000);
+        let mut limiter = RandomLimiter::new(rate, traffic.parse().unwrap(), 42);
+        let samples = 200_000;
+        let total: Duration = (0..samples).map(|_| limiter.next_gap()).sum();
+        total.as_secs_f64() / samples as f64
+    }
+
+    #[test]
+    fn test_mean_rate() {
+        for traffic in ["exp", "pareto:2.5", "onoff:0.01:0.03"] {
+            let mean = mean_gap(traffic);
+            assert!((mean - 0.001).abs() < 0.00005, "{traffic}: {mean}");
+        }
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let rate = Rate::packets(1000, 1000);
+        let mut a = RandomLimiter::new(rate, Traffic::Exponential, 7);
+        let mut b = RandomLimiter::new(rate, Traffic::Exponential, 7);
+        for _ in 0..100 {
+            assert_eq!(a.next_gap(), b.next_gap());
+        }
+        assert!("pareto:1".parse::<Traffic>().is_err());
+    }
+}
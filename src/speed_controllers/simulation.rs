@@ -0,0 +1,217 @@
+//! Drives limiters through virtual time to check what they would send
+use std::time::Duration;
+
+use super::*;
+use crate::{
+    clock::{Clock, MockClock},
+    rate::{Bitrate, Rate},
+};
+
+/// Time it takes to hand one frame to the socket
+const SEND_COST: Duration = Duration::from_nanos(100);
+
+/// Length of every simulation
+const DURATION: Duration = Duration::from_secs(2);
+
+const BITRATES: [u64; 4] = [1_000_000, 10_000_000, 100_000_000, 1_000_000_000];
+
+/// Frames of 64 and 1500 byte UDP packets and a jumbo frame
+const FRAME_SIZES: [u16; 3] = [36, 1472, 8972];
+
+/// What the limiter sent during a simulation
+struct Outcome {
+    frames: u64,
+    /// Longest run of frames sent without sleeping in between
+    max_burst: u64,
+    /// Deepest queue in front of a link draining at the target rate, bytes
+    max_queue: f64,
+}
+
+/// Sends frames for `duration` of virtual time, sleeping as `limiter` says
+fn simulate(
+    limiter: &mut impl Limiter,
+    clock: &MockClock,
+    rate: &Rate,
+    duration: Duration,
+) -> Outcome {
+    let start = clock.now();
+    let mut last = start;
+    let mut outcome = Outcome {
+        frames: 0,
+        max_burst: 0,
+        max_queue: 0.,
+    };
+    let mut burst = 0;
+    let mut queue = 0_f64;
+    while clock.since(start) < duration {
+        let now = clock.now();
+        let drained = (now - last).as_secs_f64() * rate.bytes_per_second();
+        queue = (queue - drained).max(0.) + f64::from(rate.frame_size());
+        last = now;
+        outcome.max_queue = outcome.max_queue.max(queue);
+        outcome.frames += 1;
+        burst += 1;
+        outcome.max_burst = outcome.max_burst.max(burst);
+
+        clock.advance(SEND_COST);
+        let sleep = limiter.sleep_interval();
+        if !sleep.is_zero() {
+            burst = 0;
+        }
+        clock.advance(sleep);
+    }
+    outcome
+}
+
+/// Runs `check` for the outcome of every rate and frame size
+fn matrix<L: Limiter>(limiter: impl Fn(Rate, MockClock) -> L, check: impl Fn(&Rate, &Outcome)) {
+    for bitrate in BITRATES {
+        for frame_size in FRAME_SIZES {
+            let rate = Rate::bandwidth(Bitrate(bitrate), frame_size);
+            let clock = MockClock::new();
+            let outcome = simulate(&mut limiter(rate, clock.clone()), &clock, &rate, DURATION);
+            check(&rate, &outcome);
+        }
+    }
+}
+
+/// Frames the target rate allows in a simulation
+fn expected_frames(rate: &Rate) -> f64 {
+    rate.frames_per_second() * DURATION.as_secs_f64()
+}
+
+#[test]
+fn test_burst_limiter() {
+    matrix(
+        |rate, clock| BurstLimiter::with_clock(rate, true, clock),
+        |rate, outcome| {
+            let expected = expected_frames(rate);
+            // the last burst may be cut short
+            let error = (outcome.frames as f64 - expected).abs();
+            assert!(
+                error <= expected / 100. + outcome.max_burst as f64,
+                "{rate}: sent {} frames of {expected}",
+                outcome.frames
+            );
+            // the window holds at most a second of frames
+            assert!(outcome.max_burst as f64 <= rate.frames_per_second().max(1.));
+            let burst_bytes = (outcome.max_burst + 1) as f64 * f64::from(rate.frame_size());
+            assert!(
+                outcome.max_queue <= burst_bytes,
+                "{rate}: {}",
+                outcome.max_queue
+            );
+        },
+    );
+}
+
+#[test]
+fn test_token_bucket_limiter() {
+    let depth = 10;
+    matrix(
+        |rate, clock| TokenBucketLimiter::with_clock(rate, BucketDepth::Frames(depth), clock),
+        |rate, outcome| {
+            let expected = expected_frames(rate);
+            // the full bucket goes on top of the rate
+            let error = outcome.frames as f64 - expected;
+            assert!(
+                (0. ..=expected / 100. + depth as f64).contains(&error),
+                "{rate}: sent {} frames of {expected}",
+                outcome.frames
+            );
+            let depth_bytes = (depth + 1) as f64 * f64::from(rate.frame_size());
+            assert!(
+                outcome.max_queue <= depth_bytes,
+                "{rate}: {}",
+                outcome.max_queue
+            );
+        },
+    );
+}
+
+#[test]
+fn test_token_bucket_accuracy() {
+    // 1% of the rate from 64 kbit/s to 10 Gbit/s, once the initial bucket is spent
+    let depth = 10;
+    for bitrate in [
+        64_000,
+        1_000_000,
+        100_000_000,
+        1_000_000_000,
+        10_000_000_000,
+    ] {
+        let rate = Rate::bandwidth(Bitrate(bitrate), 1472);
+        // long enough for slow rates to send thousands of frames
+        let duration = DURATION.max(Duration::from_secs_f64(10_000. / rate.frames_per_second()));
+        let clock = MockClock::new();
+        let mut limiter =
+            TokenBucketLimiter::with_clock(rate, BucketDepth::Frames(depth), clock.clone());
+        let outcome = simulate(&mut limiter, &clock, &rate, duration);
+        let expected = rate.frames_per_second() * duration.as_secs_f64();
+        let error = (outcome.frames - depth) as f64 - expected;
+        assert!(
+            error.abs() <= expected / 100.,
+            "{rate}: sent {} of {expected}",
+            outcome.frames
+        );
+    }
+}
+
+#[test]
+fn test_static_limiter() {
+    matrix(
+        |rate, _| StaticLimiter::new(rate),
+        |rate, outcome| {
+            // sleeps the whole gap on top of the time spent sending
+            let gap = 1. / rate.frames_per_second();
+            let expected = DURATION.as_secs_f64() / (gap + SEND_COST.as_secs_f64());
+            assert!((outcome.frames as f64 - expected).abs() <= 1., "{rate}");
+            assert_eq!(outcome.max_burst, 1);
+            assert!(outcome.max_queue <= f64::from(rate.frame_size()));
+        },
+    );
+}
+
+#[test]
+fn test_random_limiter() {
+    matrix(
+        |rate, clock| RandomLimiter::with_clock(rate, Traffic::Exponential, 1, clock),
+        |rate, outcome| {
+            let expected = expected_frames(rate);
+            // Poisson count, a few standard deviations off at most
+            let error = (outcome.frames as f64 - expected).abs();
+            assert!(
+                error <= 5. * expected.sqrt() + 1.,
+                "{rate}: sent {}",
+                outcome.frames
+            );
+        },
+    );
+}
+
+#[test]
+fn test_overtime_limiter() {
+    matrix(OverTimeLimiter::with_clock, |rate, outcome| {
+        // whole frames per second, 50 kbit/s less every second, gaps of whole microseconds
+        let frames_per_second = |second: u64| {
+            let bitrate = rate.bitrate().0.saturating_sub(second * 50_000);
+            (bitrate / 8 / u64::from(rate.frame_size())).max(1)
+        };
+        let mut gaps = (0..DURATION.as_secs())
+            .map(|second| Duration::from_micros(1_000_000 / frames_per_second(second)));
+        let expected: f64 = gaps
+            .clone()
+            .map(|gap| 1. / (gap + SEND_COST).as_secs_f64())
+            .sum();
+        // a frame may straddle every second
+        let error = (outcome.frames as f64 - expected).abs();
+        assert!(
+            error <= 2.,
+            "{rate}: sent {} frames of {expected}",
+            outcome.frames
+        );
+        // too fast for microseconds, frames go out back to back
+        let back_to_back = gaps.any(|gap| gap.is_zero());
+        assert!(back_to_back || outcome.max_burst == 1, "{rate}");
+    });
+}
@@ -1,4 +1,5 @@
 use super::Limiter;
+use crate::rate::{Bitrate, Rate};
 
 /// Contains Speed Limiting strategies
 use std::time::Duration;
@@ -6,17 +7,17 @@ use std::time::Duration;
 /// Naive limiter relying on spinsleep
 pub struct StaticLimiter {
     dur: Duration,
+    target: Option<Bitrate>,
 }
 
 impl StaticLimiter {
-    pub fn new(speed: u32, mtu: u16) -> Self {
-        Self {
-            dur: Duration::from_micros(if speed == 0 {
-                0
-            } else {
-                1_000_000 / ((speed as u64 * 1024 / 8) / mtu as u64)
-            }),
-        }
+    pub fn new(rate: Rate) -> Self {
+        let mut limiter = Self {
+            dur: Duration::ZERO,
+            target: None,
+        };
+        limiter.set_rate(rate);
+        limiter
     }
 }
 
@@ -24,4 +25,18 @@ impl Limiter for StaticLimiter {
     fn sleep_interval(&mut self) -> Duration {
         self.dur
     }
+
+    fn target(&self) -> Option<Bitrate> {
+        self.target
+    }
+
+    fn set_rate(&mut self, rate: Rate) -> bool {
+        self.dur = if rate.is_unlimited() {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(1. / rate.frames_per_second())
+        };
+        self.target = (!rate.is_unlimited()).then(|| rate.bitrate());
+        true
+    }
 }
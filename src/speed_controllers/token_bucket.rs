@@ -0,0 +1,140 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use super::Limiter;
+use crate::{
+    clock::{Clock, SystemClock},
+    rate::{Bitrate, Rate},
+};
+
+/// Depth of the token bucket
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BucketDepth {
+    Bytes(u64),
+    Frames(u64),
+}
+
+/// Parses `<n>` as bytes and `<n>f` as frames
+impl FromStr for BucketDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (num, frames) = match s.strip_suffix('f') {
+            Some(num) => (num, true),
+            None => (s, false),
+        };
+        let num = num
+            .parse()
+            .map_err(|e| format!("invalid bucket depth `{s}`: {e}"))?;
+        Ok(if frames {
+            Self::Frames(num)
+        } else {
+            Self::Bytes(num)
+        })
+    }
+}
+
+/// Policer-like limiter, sends while tokens are available
+///
+/// Tokens are refilled from elapsed time, so it catches up after
+/// scheduling hiccups, but never by more than the bucket depth.
+pub struct TokenBucketLimiter<C: Clock = SystemClock> {
+    /// Refill rate, bytes per second
+    rate: f64,
+    bucket: BucketDepth,
+    /// Bucket capacity, bytes
+    depth: f64,
+    frame_size: f64,
+    tokens: f64,
+    last: Instant,
+    disabled: bool,
+    target: Option<Bitrate>,
+    clock: C,
+}
+
+impl TokenBucketLimiter {
+    pub fn new(rate: Rate, depth: BucketDepth) -> Self {
+        Self::with_clock(rate, depth, SystemClock)
+    }
+}
+
+impl<C: Clock> TokenBucketLimiter<C> {
+    pub fn with_clock(rate: Rate, depth: BucketDepth, clock: C) -> Self {
+        let mut limiter = Self {
+            rate: 0.,
+            bucket: depth,
+            depth: 0.,
+            frame_size: 0.,
+            tokens: f64::INFINITY,
+            last: clock.now(),
+            disabled: true,
+            target: None,
+            clock,
+        };
+        limiter.set_rate(rate);
+        limiter
+    }
+}
+
+impl<C: Clock> Limiter for TokenBucketLimiter<C> {
+    fn sleep_interval(&mut self) -> Duration {
+        if self.disabled {
+            return Duration::ZERO;
+        }
+        let now = self.clock.now();
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.depth) - self.frame_size;
+        if self.tokens >= 0. {
+            Duration::ZERO
+        } else {
+            // sleep until the debt is paid off
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    fn target(&self) -> Option<Bitrate> {
+        self.target
+    }
+
+    fn set_rate(&mut self, rate: Rate) -> bool {
+        let frame_size = f64::from(rate.frame_size());
+        let depth = match self.bucket {
+            BucketDepth::Bytes(bytes) => bytes as f64,
+            BucketDepth::Frames(frames) => frames as f64 * frame_size,
+        };
+        // Bucket has to fit at least one frame, otherwise nothing would be sent
+        self.depth = depth.max(frame_size);
+        self.tokens = self.tokens.min(self.depth);
+        self.frame_size = frame_size;
+        self.rate = rate.bytes_per_second();
+        self.disabled = rate.is_unlimited();
+        self.target = (!rate.is_unlimited()).then(|| rate.bitrate());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_depth_parse() {
+        assert_eq!("1500".parse(), Ok(BucketDepth::Bytes(1500)));
+        assert_eq!("10f".parse(), Ok(BucketDepth::Frames(10)));
+        assert!("f".parse::<BucketDepth>().is_err());
+    }
+
+    #[test]
+    fn test_bursts_bucket_depth() {
+        let rate = Rate::packets(1, 1000);
+        let mut limiter = TokenBucketLimiter::new(rate, BucketDepth::Frames(3));
+        for _ in 0..3 {
+            assert_eq!(limiter.sleep_interval(), Duration::ZERO);
+        }
+        // 1 frame per second, so the next frame has to wait about a second
+        assert!(limiter.sleep_interval() > Duration::from_millis(900));
+    }
+}
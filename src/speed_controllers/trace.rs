@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+use super::Limiter;
+use crate::{
+    clock::{Clock, SystemClock},
+    trace::Trace,
+};
+
+/// Limiter replaying timings and sizes of a recorded trace
+pub struct TraceLimiter<C: Clock = SystemClock> {
+    /// Departure time since the start and record size of every frame
+    frames: Vec<(Duration, u16)>,
+    /// Frame to be sent next
+    next: usize,
+    start: Instant,
+    /// Trace duration with an average gap, so loops don't overlap
+    period: Duration,
+    looped: bool,
+    done: bool,
+    clock: C,
+}
+
+impl TraceLimiter {
+    pub fn new(trace: &Trace, headers: u16, min: u16, max: u16, looped: bool) -> Self {
+        Self::with_clock(trace, headers, min, max, looped, SystemClock)
+    }
+}
+
+impl<C: Clock> TraceLimiter<C> {
+    /// Longest stall the limiter would catch up after
+    const MAX_DEBT: Duration = Duration::from_millis(100);
+
+    /// Packet lengths are turned into record sizes without `headers`,
+    /// clamped to `min..=max`
+    pub fn with_clock(
+        trace: &Trace,
+        headers: u16,
+        min: u16,
+        max: u16,
+        looped: bool,
+        clock: C,
+    ) -> Self {
+        let frames: Vec<_> = trace
+            .packets
+            .iter()
+            .map(|(time, len)| (*time, len.saturating_sub(headers).clamp(min, max)))
+            .collect();
+        let last = frames.last().map(|(time, _)| *time).unwrap_or_default();
+        let gap = match frames.len() {
+            0 | 1 => Duration::from_secs(1),
+            len => last / (len as u32 - 1),
+        };
+        Self {
+            frames,
+            next: 0,
+            start: clock.now(),
+            period: last + gap,
+            looped,
+            done: false,
+            clock,
+        }
+    }
+}
+
+impl<C: Clock> Limiter for TraceLimiter<C> {
+    fn sleep_interval(&mut self) -> Duration {
+        self.next += 1;
+        if self.next == self.frames.len() {
+            if !self.looped {
+                self.done = true;
+                return Duration::ZERO;
+            }
+            self.next = 0;
+            self.start += self.period;
+        }
+        let now = self.clock.now();
+        let departure = self.start + self.frames[self.next].0;
+        if let Some(debt) = now.checked_duration_since(departure + Self::MAX_DEBT) {
+            self.start += debt;
+        }
+        (self.start + self.frames[self.next].0).saturating_duration_since(now)
+    }
+
+    fn frame_size(&self) -> Option<u16> {
+        self.frames.get(self.next).map(|(_, size)| *size)
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loops_over_sizes() {
+        let trace = Trace {
+            packets: vec![
+                (Duration::ZERO, 100),
+                (Duration::ZERO, 1500),
+                (Duration::ZERO, 20),
+            ],
+        };
+        let mut limiter = TraceLimiter::new(&trace, 28, 10, 1472, true);
+        let mut sizes = vec![];
+        for _ in 0..4 {
+            sizes.push(limiter.frame_size().unwrap());
+            limiter.sleep_interval();
+        }
+        assert_eq!(sizes, [72, 1472, 10, 72]);
+
+        let mut limiter = TraceLimiter::new(&trace, 28, 10, 1472, false);
+        for _ in 0..3 {
+            assert!(!limiter.is_done());
+            limiter.sleep_interval();
+        }
+        assert!(limiter.is_done());
+    }
+}
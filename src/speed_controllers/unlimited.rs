@@ -11,6 +11,12 @@ impl UnLimiter {
     }
 }
 
+impl Default for UnLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Limiter for UnLimiter {
     fn sleep_interval(&mut self) -> Duration {
         Duration::ZERO
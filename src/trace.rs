@@ -0,0 +1,167 @@
+/// Module for recorded traffic traces
+use std::{fs, time::Duration};
+
+use anyhow::{bail, ensure, Context, Result};
+
+/// Timestamps and IP packet lengths of recorded traffic
+#[derive(Debug, PartialEq)]
+pub struct Trace {
+    /// Time since the first packet and IP packet length
+    pub packets: Vec<(Duration, u16)>,
+}
+
+/// Link layer header lengths for the supported pcap link types
+fn link_header_len(link_type: u32) -> Result<usize> {
+    Ok(match link_type {
+        // Ethernet
+        1 => 14,
+        // Raw IP
+        101 | 228 | 229 => 0,
+        // Linux cooked capture
+        113 => 16,
+        // Linux cooked capture v2
+        276 => 20,
+        _ => bail!("unsupported pcap link type {link_type}"),
+    })
+}
+
+impl Trace {
+    /// Reads a pcap file or a text file with `<seconds> <length>` on every line
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = fs::read(path).with_context(|| format!("can't read trace {path}"))?;
+        let trace = if content.len() >= 4 && Self::pcap_header(&content).is_some() {
+            Self::from_pcap(&content)
+        } else {
+            Self::from_text(&String::from_utf8_lossy(&content))
+        }
+        .with_context(|| format!("invalid trace {path}"))?;
+        ensure!(!trace.packets.is_empty(), "no packets in trace {path}");
+        Ok(trace)
+    }
+
+    /// Parses `<seconds> <length>` lines, empty lines and `#` comments are skipped
+    pub fn from_text(content: &str) -> Result<Self> {
+        let mut packets = vec![];
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse = || -> Option<(Duration, u16)> {
+                let (time, len) = line.split_once(char::is_whitespace)?;
+                let time = Duration::try_from_secs_f64(time.parse().ok()?).ok()?;
+                Some((time, len.trim().parse().ok()?))
+            };
+            packets.push(
+                parse()
+                    .with_context(|| format!("line {}: expected `<seconds> <length>`", n + 1))?,
+            );
+        }
+        Ok(Self::relative(packets))
+    }
+
+    /// Returns whether the file is big-endian and has nanosecond timestamps
+    fn pcap_header(content: &[u8]) -> Option<(bool, bool)> {
+        match content[..4] {
+            [0xa1, 0xb2, 0xc3, 0xd4] => Some((true, false)),
+            [0xd4, 0xc3, 0xb2, 0xa1] => Some((false, false)),
+            [0xa1, 0xb2, 0x3c, 0x4d] => Some((true, true)),
+            [0x4d, 0x3c, 0xb2, 0xa1] => Some((false, true)),
+            _ => None,
+        }
+    }
+
+    /// Parses timestamps and original lengths of a classic pcap file
+    pub fn from_pcap(content: &[u8]) -> Result<Self> {
+        let (big_endian, nanos) = Self::pcap_header(content).context("not a pcap file")?;
+        let read_u32 = |offset: usize| -> Result<u32> {
+            let bytes = content
+                .get(offset..offset + 4)
+                .context("truncated pcap file")?
+                .try_into()?;
+            Ok(match big_endian {
+                true => u32::from_be_bytes(bytes),
+                false => u32::from_le_bytes(bytes),
+            })
+        };
+        let link_header = link_header_len(read_u32(20)?)?;
+        let mut packets = vec![];
+        let mut offset = 24;
+        while offset < content.len() {
+            let secs = read_u32(offset)?;
+            let fraction = read_u32(offset + 4)?;
+            let captured = read_u32(offset + 8)? as usize;
+            let original = read_u32(offset + 12)? as usize;
+            let time = Duration::from_secs(secs.into())
+                + match nanos {
+                    true => Duration::from_nanos(fraction.into()),
+                    false => Duration::from_micros(fraction.into()),
+                };
+            let len = original.saturating_sub(link_header).min(u16::MAX.into());
+            packets.push((time, len as u16));
+            offset += 16 + captured;
+        }
+        Ok(Self::relative(packets))
+    }
+
+    /// Orders packets by time, keeping the order of equal timestamps,
+    /// and makes timestamps relative to the first packet
+    fn relative(mut packets: Vec<(Duration, u16)>) -> Self {
+        packets.sort_by_key(|(time, _)| *time);
+        let first = packets.first().map(|(time, _)| *time).unwrap_or_default();
+        Self {
+            packets: packets
+                .into_iter()
+                .map(|(time, len)| (time.saturating_sub(first), len))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_trace() {
+        let trace = Trace::from_text("# time length\n10.5 1500\n\n10.75 64\n").unwrap();
+        assert_eq!(
+            trace.packets,
+            vec![(Duration::ZERO, 1500), (Duration::from_millis(250), 64)]
+        );
+        assert!(Trace::from_text("1.0").is_err());
+    }
+
+    #[test]
+    fn test_unordered_text_trace() {
+        let trace = Trace::from_text("2 100\n1 200\n3 300\n1 400\n").unwrap();
+        assert_eq!(
+            trace.packets,
+            vec![
+                (Duration::ZERO, 200),
+                (Duration::ZERO, 400),
+                (Duration::from_secs(1), 100),
+                (Duration::from_secs(2), 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pcap_trace() {
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&65535_u32.to_le_bytes());
+        pcap.extend_from_slice(&1_u32.to_le_bytes());
+        for (secs, micros, len) in [(100_u32, 0_u32, 1514_u32), (100, 500_000, 78)] {
+            for field in [secs, micros, 4, len] {
+                pcap.extend_from_slice(&field.to_le_bytes());
+            }
+            pcap.extend_from_slice(&[0; 4]);
+        }
+        let trace = Trace::from_pcap(&pcap).unwrap();
+        assert_eq!(
+            trace.packets,
+            vec![(Duration::ZERO, 1500), (Duration::from_millis(500), 64)]
+        );
+    }
+}
@@ -3,6 +3,7 @@ use std::time::Duration;
 use loss_tester_rs::speed_controllers::{self, Limiter};
 
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn basic_test() {
     assert!(true)
 }